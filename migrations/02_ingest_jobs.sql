@@ -0,0 +1,19 @@
+-- Ingest jobs queue, claimed by the in-process worker pool with SKIP LOCKED
+CREATE TABLE gridwalk.ingest_jobs (
+    id UUID PRIMARY KEY,
+    layer_id UUID NOT NULL REFERENCES gridwalk.layers(id) ON DELETE CASCADE,
+    status VARCHAR(50) NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 3,
+    last_error TEXT,
+    run_after TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX ingest_jobs_pending_idx ON gridwalk.ingest_jobs (run_after)
+    WHERE status = 'pending';
+
+CREATE TRIGGER ingest_jobs_updated_at
+    BEFORE UPDATE ON gridwalk.ingest_jobs
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
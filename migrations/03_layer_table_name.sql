@@ -0,0 +1,2 @@
+-- Data table created for the layer in the layer data schema
+ALTER TABLE gridwalk.layers ADD COLUMN table_name VARCHAR(255);
@@ -0,0 +1,3 @@
+-- Uploads created with Upload-Defer-Length have no size until a later PATCH
+ALTER TABLE gridwalk.layers ALTER COLUMN total_size DROP NOT NULL;
+ALTER TABLE gridwalk.layers ALTER COLUMN total_size DROP DEFAULT;
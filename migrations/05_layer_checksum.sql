@@ -0,0 +1,2 @@
+-- SHA-256 digest of the complete uploaded file
+ALTER TABLE gridwalk.layers ADD COLUMN checksum VARCHAR(64);
@@ -0,0 +1,5 @@
+-- Expiry for unfinished uploads, enforced by the upload janitor
+ALTER TABLE gridwalk.layers ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX layers_uploading_expires_at_idx ON gridwalk.layers (expires_at)
+    WHERE status = 'Uploading';
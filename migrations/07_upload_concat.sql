@@ -0,0 +1,2 @@
+-- TUS concatenation: 'partial', or 'final;<partial urls>' for joined uploads
+ALTER TABLE gridwalk.layers ADD COLUMN upload_concat TEXT;
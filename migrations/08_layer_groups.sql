@@ -0,0 +1,8 @@
+-- Source layers of a multi-layer upload become child layers of the upload
+ALTER TABLE gridwalk.layers ADD COLUMN parent_id UUID REFERENCES gridwalk.layers(id) ON DELETE CASCADE;
+ALTER TABLE gridwalk.layers ADD COLUMN source_layer TEXT;
+
+-- Options from Upload-Metadata that control how the upload is ingested
+ALTER TABLE gridwalk.layers ADD COLUMN ingest_options JSONB;
+
+CREATE INDEX idx_layers_parent_id ON gridwalk.layers(parent_id) WHERE parent_id IS NOT NULL;
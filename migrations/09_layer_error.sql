@@ -0,0 +1,2 @@
+-- Reason the last ingest of a layer failed
+ALTER TABLE gridwalk.layers ADD COLUMN error TEXT;
@@ -0,0 +1,2 @@
+-- Summary of the last ingest: skipped features, unsupported fields, ...
+ALTER TABLE gridwalk.layers ADD COLUMN ingest_report JSONB;
@@ -0,0 +1,2 @@
+-- EPSG code of the uploaded data before it was reprojected for storage
+ALTER TABLE gridwalk.layers ADD COLUMN source_srid INTEGER;
@@ -0,0 +1,8 @@
+-- Spatial metadata filled in after a layer is loaded
+ALTER TABLE gridwalk.layers ADD COLUMN srid INTEGER;
+-- Extent in EPSG:4326 as [min x, min y, max x, max y]
+ALTER TABLE gridwalk.layers ADD COLUMN bbox DOUBLE PRECISION[];
+ALTER TABLE gridwalk.layers ADD COLUMN geometry_type VARCHAR(50);
+ALTER TABLE gridwalk.layers ADD COLUMN feature_count BIGINT;
+-- Columns of the layer table: name, type and source field name
+ALTER TABLE gridwalk.layers ADD COLUMN fields JSONB;
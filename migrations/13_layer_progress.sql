@@ -0,0 +1,2 @@
+-- Progress of the running or last ingest: stage, feature counts, timestamps
+ALTER TABLE gridwalk.layers ADD COLUMN progress JSONB;
@@ -0,0 +1,7 @@
+-- Ingest errors become structured: stage, message and the failing feature
+ALTER TABLE gridwalk.layers
+    ALTER COLUMN error TYPE JSONB
+    USING CASE
+        WHEN error IS NULL THEN NULL
+        ELSE jsonb_build_object('stage', 'loading', 'message', error, 'failed_at', updated_at)
+    END;
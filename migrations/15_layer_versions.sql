@@ -0,0 +1,20 @@
+-- Immutable snapshots of a layer's data, one table per version in
+-- gridwalk_layer_data. The layer's own table name becomes a view over the
+-- active version.
+CREATE TABLE gridwalk.layer_versions (
+    layer_id UUID NOT NULL REFERENCES gridwalk.layers(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    table_name VARCHAR(255) NOT NULL,
+    -- Upload that created the version, NULL for the layer's original data
+    upload_id UUID,
+    mode VARCHAR(50) NOT NULL,
+    srid INTEGER,
+    bbox DOUBLE PRECISION[],
+    geometry_type VARCHAR(50),
+    feature_count BIGINT NOT NULL,
+    fields JSONB,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (layer_id, version)
+);
+
+ALTER TABLE gridwalk.layers ADD COLUMN current_version INTEGER;
@@ -0,0 +1,7 @@
+-- Lease on a running ingest job. A worker claims a job by moving it to
+-- 'running' and keeps extending the lease while it works, a running job whose
+-- lease ran out belonged to a worker that died and is claimed again
+ALTER TABLE gridwalk.ingest_jobs ADD COLUMN locked_until TIMESTAMPTZ;
+
+CREATE INDEX ingest_jobs_running_idx ON gridwalk.ingest_jobs (locked_until)
+    WHERE status = 'running';
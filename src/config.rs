@@ -6,9 +6,10 @@ use dotenvy::dotenv;
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
 use std::env;
+use std::fmt::Display;
 use std::fs;
-use std::num::ParseIntError;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use thiserror::Error;
 use tracing::info;
@@ -17,12 +18,21 @@ use tracing::info;
 pub struct AppState {
     pub app_db: Arc<PgPool>,
     pub connection: Arc<Connector>,
+    /// Schema in the connection database that holds the layer data tables
+    pub layer_schema: String,
     pub temp_data_path: Arc<PathBuf>,
+    pub max_upload_size: i64,
+    pub max_chunk_size: i64,
+    pub upload_expiry_secs: i64,
+    pub janitor_interval_secs: u64,
+    pub ingest_config: IngestConfig,
 }
 
 impl AppState {
     pub async fn new(config: Config) -> Result<Self> {
         let app_db = create_app_db_pool(&config).await;
+        // Ingests write to the same schema the connector serves tiles from
+        let layer_schema = config.postgis_db_config.schema.clone();
         let connector =
             gridwalk_core::connector::postgis::PostgisConnector::new(config.postgis_db_config)
                 .await?;
@@ -34,7 +44,13 @@ impl AppState {
         Ok(Self {
             app_db,
             connection: Arc::new(connector),
+            layer_schema,
             temp_data_path: config.temp_data_path,
+            max_upload_size: config.max_upload_size,
+            max_chunk_size: config.max_chunk_size,
+            upload_expiry_secs: config.upload_expiry_secs,
+            janitor_interval_secs: config.janitor_interval_secs,
+            ingest_config: config.ingest_config,
         })
     }
 }
@@ -44,6 +60,26 @@ pub struct Config {
     pub app_db_config: PostgresConfig,
     pub postgis_db_config: PostgresConfig,
     pub temp_data_path: Arc<PathBuf>,
+    pub max_upload_size: i64,
+    pub max_chunk_size: i64,
+    pub upload_expiry_secs: i64,
+    pub janitor_interval_secs: u64,
+    pub ingest_config: IngestConfig,
+}
+
+/// Settings for the background ingest worker pool
+#[derive(Debug, Clone)]
+pub struct IngestConfig {
+    pub workers: usize,
+    pub max_attempts: i32,
+    pub poll_interval_secs: u64,
+    pub retry_backoff_secs: i64,
+    /// Seconds a claimed job stays reserved for its worker without a heartbeat
+    pub lease_secs: i64,
+    /// EPSG code all layer geometries are reprojected to
+    pub storage_srid: i32,
+    /// Number of features between progress updates on the layer
+    pub progress_interval: u64,
 }
 
 #[derive(Debug, Error)]
@@ -68,29 +104,15 @@ impl Config {
             .map_err(|_| ConfigError::MissingVar("DATABASE_HOST".to_string()))?;
         let database_name = env::var("DATABASE_NAME")
             .map_err(|_| ConfigError::MissingVar("DATABASE_NAME".to_string()))?;
-        let port = env::var("DATABASE_PORT").unwrap_or_else(|_| "5432".to_string());
-
-        let port = port.parse::<u16>().map_err(|e: ParseIntError| {
-            ConfigError::InvalidValue("DATABASE_PORT".to_string(), e.to_string())
-        })?;
+        let port = env_or::<u16>("DATABASE_PORT", 5432)?;
         let disable_ssl = env::var("DATABASE_DISABLE_SSL")
             .unwrap_or_else(|_| "false".to_string())
             .to_lowercase()
             == "true";
 
-        let max_app_db_connections = env::var("DATABASE_MAX_CONNECTIONS")
-            .unwrap_or_else(|_| "20".to_string())
-            .parse::<u32>()
-            .map_err(|e: ParseIntError| {
-                ConfigError::InvalidValue("PG_MAX_CONNECTIONS".to_string(), e.to_string())
-            })?;
+        let max_app_db_connections = env_or::<u32>("DATABASE_MAX_CONNECTIONS", 20)?;
 
-        let max_postgis_connections = env::var("POSTGIS_MAX_CONNECTIONS")
-            .unwrap_or_else(|_| "10".to_string())
-            .parse::<u32>()
-            .map_err(|e: ParseIntError| {
-                ConfigError::InvalidValue("POSTGIS_MAX_CONNECTIONS".to_string(), e.to_string())
-            })?;
+        let max_postgis_connections = env_or::<u32>("POSTGIS_MAX_CONNECTIONS", 10)?;
 
         let app_db_schema = env::var("DATABASE_SCHEMA").unwrap_or_else(|_| "public".to_string());
         let postgis_layer_schema =
@@ -138,14 +160,68 @@ impl Config {
 
         let temp_data_path = Arc::new(temp_data_path_buf);
 
+        // Largest upload accepted, advertised to TUS clients as Tus-Max-Size
+        let max_upload_size = env_or::<i64>("MAX_UPLOAD_SIZE", 10737418240)?;
+
+        // Largest number of bytes accepted in a single PATCH request body
+        let max_chunk_size = env_or::<i64>("MAX_CHUNK_SIZE", 536870912)?;
+
+        // Unfinished uploads expire after this many seconds without a PATCH
+        let upload_expiry_secs = env_or::<i64>("UPLOAD_EXPIRY_SECS", 86400)?;
+
+        let janitor_interval_secs = env_or::<u64>("UPLOAD_JANITOR_INTERVAL_SECS", 600)?;
+
+        let ingest_workers = env_or::<usize>("INGEST_WORKERS", 2)?;
+
+        let ingest_max_attempts = env_or::<i32>("INGEST_MAX_ATTEMPTS", 3)?;
+
+        let ingest_poll_interval_secs = env_or::<u64>("INGEST_POLL_INTERVAL_SECS", 5)?;
+
+        let ingest_retry_backoff_secs = env_or::<i64>("INGEST_RETRY_BACKOFF_SECS", 30)?;
+
+        let ingest_lease_secs = env_or::<i64>("INGEST_LEASE_SECS", 300)?;
+
+        let storage_srid = env_or::<i32>("STORAGE_SRID", 4326)?;
+
+        let ingest_progress_interval = env_or::<u64>("INGEST_PROGRESS_INTERVAL", 10000)?;
+
+        let ingest_config = IngestConfig {
+            workers: ingest_workers,
+            max_attempts: ingest_max_attempts,
+            poll_interval_secs: ingest_poll_interval_secs,
+            retry_backoff_secs: ingest_retry_backoff_secs,
+            lease_secs: ingest_lease_secs,
+            storage_srid,
+            progress_interval: ingest_progress_interval,
+        };
+
         Ok(Config {
             app_db_config,
             postgis_db_config,
             temp_data_path,
+            max_upload_size,
+            max_chunk_size,
+            upload_expiry_secs,
+            janitor_interval_secs,
+            ingest_config,
         })
     }
 }
 
+/// Parse an environment variable, `default` when it is not set
+fn env_or<T>(name: &str, default: T) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e: T::Err| ConfigError::InvalidValue(name.to_string(), e.to_string())),
+        Err(_) => Ok(default),
+    }
+}
+
 pub async fn create_app_db_pool(config: &Config) -> Arc<PgPool> {
     let database_url = format!(
         "postgresql://{}:{}@{}:{}/{}",
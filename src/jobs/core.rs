@@ -0,0 +1,177 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use strum_macros::{Display, EnumString};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Display, Serialize, Deserialize, EnumString, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestJob {
+    pub id: Uuid,
+    pub layer_id: Uuid,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_after: chrono::DateTime<chrono::Utc>,
+    /// End of the lease held by the worker running the job
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl<'r> FromRow<'r, PgRow> for IngestJob {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(IngestJob {
+            id: row.try_get("id")?,
+            layer_id: row.try_get("layer_id")?,
+            status: {
+                let status_str: String = row.try_get("status")?;
+                status_str.parse().map_err(|e| {
+                    sqlx::Error::Decode(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Invalid job status value: {} - {}", status_str, e),
+                    )))
+                })?
+            },
+            attempts: row.try_get("attempts")?,
+            max_attempts: row.try_get("max_attempts")?,
+            last_error: row.try_get("last_error")?,
+            run_after: row.try_get("run_after")?,
+            locked_until: row.try_get("locked_until")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+impl IngestJob {
+    pub fn new(layer_id: Uuid, max_attempts: i32) -> Self {
+        let now = chrono::Utc::now();
+        IngestJob {
+            id: Uuid::new_v4(),
+            layer_id,
+            status: JobStatus::Pending,
+            attempts: 0,
+            max_attempts,
+            last_error: None,
+            run_after: now,
+            locked_until: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub async fn save<'e, E>(&self, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "INSERT INTO gridwalk.ingest_jobs (id, layer_id, status, attempts, max_attempts, last_error, run_after, locked_until, created_at, updated_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+                     ON CONFLICT (id) DO UPDATE SET \
+                     status = EXCLUDED.status, \
+                     attempts = EXCLUDED.attempts, \
+                     max_attempts = EXCLUDED.max_attempts, \
+                     last_error = EXCLUDED.last_error, \
+                     run_after = EXCLUDED.run_after, \
+                     locked_until = EXCLUDED.locked_until";
+
+        sqlx::query(query)
+            .bind(self.id)
+            .bind(self.layer_id)
+            .bind(self.status.to_string())
+            .bind(self.attempts)
+            .bind(self.max_attempts)
+            .bind(&self.last_error)
+            .bind(self.run_after)
+            .bind(self.locked_until)
+            .bind(self.created_at)
+            .bind(self.updated_at)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Claim the oldest runnable job, or a running job whose lease ran out
+    /// because its worker died. The job moves to `running` with the attempt
+    /// counted and a lease of `lease_secs`, committed straight away so an
+    /// attempt that never finishes still counts towards `max_attempts`.
+    pub async fn claim<'e, E>(lease_secs: i64, executor: E) -> Result<Option<Self>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.ingest_jobs \
+                     SET status = $2, attempts = attempts + 1, \
+                     locked_until = NOW() + make_interval(secs => $3) \
+                     WHERE id = ( \
+                         SELECT id FROM gridwalk.ingest_jobs \
+                         WHERE (status = $1 AND run_after <= NOW()) \
+                         OR (status = $2 AND locked_until < NOW()) \
+                         ORDER BY run_after, created_at \
+                         FOR UPDATE SKIP LOCKED \
+                         LIMIT 1) \
+                     RETURNING *";
+
+        let job = sqlx::query_as::<_, IngestJob>(query)
+            .bind(JobStatus::Pending.to_string())
+            .bind(JobStatus::Running.to_string())
+            .bind(lease_secs as f64)
+            .fetch_optional(executor)
+            .await?;
+        Ok(job)
+    }
+
+    /// Record the outcome of the attempt a worker claimed and release its
+    /// lease. Returns `false`, writing nothing, when the job is no longer
+    /// running that attempt, e.g. its lease ran out and another worker
+    /// claimed it again.
+    pub async fn finish<'e, E>(&self, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.ingest_jobs \
+                     SET status = $4, last_error = $5, run_after = $6, locked_until = NULL, \
+                     updated_at = NOW() \
+                     WHERE id = $1 AND status = $2 AND attempts = $3";
+
+        let result = sqlx::query(query)
+            .bind(self.id)
+            .bind(JobStatus::Running.to_string())
+            .bind(self.attempts)
+            .bind(self.status.to_string())
+            .bind(&self.last_error)
+            .bind(self.run_after)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Push the lease of a running job `lease_secs` into the future. Returns
+    /// `false` when the job is not running anymore.
+    pub async fn extend_lease<'e, E>(id: Uuid, lease_secs: i64, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.ingest_jobs \
+                     SET locked_until = NOW() + make_interval(secs => $3) \
+                     WHERE id = $1 AND status = $2";
+
+        let result = sqlx::query(query)
+            .bind(id)
+            .bind(JobStatus::Running.to_string())
+            .bind(lease_secs as f64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
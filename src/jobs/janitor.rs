@@ -0,0 +1,139 @@
+use crate::config::AppState;
+use crate::layer::Layer;
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Start the periodic task that removes expired, unfinished uploads, the
+/// files of finished ones and files left by validation requests.
+pub fn spawn_janitor(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(state.janitor_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = remove_expired_uploads(&state).await {
+                error!("Upload janitor error: {}", e);
+            }
+            if let Err(e) = remove_finished_uploads(&state).await {
+                error!("Upload janitor error: {}", e);
+            }
+            if let Err(e) = remove_preflight_leftovers(&state).await {
+                error!("Upload janitor error: {}", e);
+            }
+        }
+    });
+}
+
+/// Cancel expired uploads and delete their temp files.
+async fn remove_expired_uploads(state: &AppState) -> Result<()> {
+    let expired = Layer::cancel_expired(&*state.app_db).await?;
+
+    for layer_id in &expired {
+        let upload_file_path = state.temp_data_path.join(layer_id.to_string());
+        match fs::remove_file(&upload_file_path).await {
+            Ok(()) => info!(
+                "Removed expired upload {} ({:?})",
+                layer_id, upload_file_path
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("Cancelled expired upload {} (no temp file)", layer_id)
+            }
+            Err(e) => warn!(
+                "Cancelled expired upload {} but failed to remove {:?}: {}",
+                layer_id, upload_file_path, e
+            ),
+        }
+    }
+
+    if !expired.is_empty() {
+        info!("Upload janitor cancelled {} expired uploads", expired.len());
+    }
+    Ok(())
+}
+
+/// Delete the temp files of uploads that were loaded, or that failed and
+/// were not retried within the upload expiry, along with the directories
+/// their archives were extracted into. The worker removes these itself,
+/// this catches the ones it could not.
+async fn remove_finished_uploads(state: &AppState) -> Result<()> {
+    let mut upload_ids = Vec::new();
+    let mut entries = fs::read_dir(&*state.temp_data_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        // Upload files and extracted archives are named after their layer,
+        // other files are skipped
+        if let Some(layer_id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Uuid::parse_str(name.strip_suffix(".extract").unwrap_or(name)).ok())
+        {
+            upload_ids.push(layer_id);
+        }
+    }
+    upload_ids.sort();
+    upload_ids.dedup();
+    if upload_ids.is_empty() {
+        return Ok(());
+    }
+
+    let finished =
+        Layer::finished_uploads(&upload_ids, state.upload_expiry_secs, &*state.app_db).await?;
+    for layer_id in &finished {
+        for path in [
+            state.temp_data_path.join(layer_id.to_string()),
+            state.temp_data_path.join(format!("{}.extract", layer_id)),
+        ] {
+            match remove_path(&path).await {
+                Ok(()) => info!("Removed finished upload {} ({:?})", layer_id, path),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove finished upload {:?}: {}", path, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Delete files checked by the validate endpoint, and their extracted
+/// archives, that are older than the upload expiry. The request removes
+/// them itself, this catches the ones left by requests that never finished.
+async fn remove_preflight_leftovers(state: &AppState) -> Result<()> {
+    let max_age = Duration::from_secs(state.upload_expiry_secs.max(0) as u64);
+    let mut entries = fs::read_dir(&*state.temp_data_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let is_preflight = entry.file_name().to_str().is_some_and(|name| {
+            name.ends_with(".preflight") || name.ends_with(".preflight.extract")
+        });
+        if !is_preflight {
+            continue;
+        }
+        let expired = entry
+            .metadata()
+            .await?
+            .modified()?
+            .elapsed()
+            .is_ok_and(|age| age > max_age);
+        if !expired {
+            continue;
+        }
+
+        let path = entry.path();
+        match remove_path(&path).await {
+            Ok(()) => info!("Removed preflight leftover {:?}", path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove preflight leftover {:?}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+/// Remove a temp file, or a directory with everything in it.
+async fn remove_path(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
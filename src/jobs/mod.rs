@@ -0,0 +1,7 @@
+mod core;
+mod janitor;
+mod worker;
+
+pub use core::*;
+pub use janitor::*;
+pub use worker::*;
@@ -0,0 +1,302 @@
+use crate::config::AppState;
+use crate::jobs::{IngestJob, JobStatus};
+use crate::layer::ingest::{IngestError, IngestOutcome, IngestStage, ProgressTracker};
+use crate::layer::{Layer, LayerStatus, ingest};
+use anyhow::{Result, anyhow};
+use gridwalk_core::LayerCore;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Longest wait before a failed ingest is retried
+const MAX_RETRY_BACKOFF_SECS: i64 = 6 * 60 * 60;
+
+/// Start the ingest worker pool. Each worker polls the job table and
+/// processes one job at a time.
+pub fn spawn_workers(state: Arc<AppState>) {
+    for worker_id in 0..state.ingest_config.workers {
+        let state = state.clone();
+        tokio::spawn(async move {
+            info!("Ingest worker {} started", worker_id);
+            loop {
+                match run_next_job(&state).await {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => error!("Ingest worker {} error: {}", worker_id, e),
+                }
+                tokio::time::sleep(Duration::from_secs(state.ingest_config.poll_interval_secs))
+                    .await;
+            }
+        });
+    }
+}
+
+/// Claim and process a single job. Returns `false` when the queue is empty.
+async fn run_next_job(state: &AppState) -> Result<bool> {
+    let lease_secs = state.ingest_config.lease_secs;
+    let Some(mut job) = IngestJob::claim(lease_secs, &*state.app_db).await? else {
+        return Ok(false);
+    };
+
+    info!(
+        "Running ingest job {} for layer {} (attempt {}/{})",
+        job.id, job.layer_id, job.attempts, job.max_attempts
+    );
+
+    // No app database transaction is held during the ingest, the lease keeps
+    // other workers away. A job left running by a worker that died is claimed
+    // again once the lease runs out.
+    let heartbeat = spawn_lease_heartbeat(state.app_db.clone(), job.id, lease_secs);
+    let result = run_job(state, &mut job).await;
+    heartbeat.abort();
+
+    // A job that could not record its outcome goes back to the queue. Once it
+    // is out of attempts, the next claim fails it along with its layer.
+    if let Err(e) = &result {
+        job.status = JobStatus::Pending;
+        job.last_error = Some(e.to_string());
+        job.run_after = chrono::Utc::now()
+            + chrono::Duration::seconds(retry_backoff(
+                state.ingest_config.retry_backoff_secs,
+                job.attempts,
+            ));
+    }
+
+    if !job.finish(&*state.app_db).await? {
+        warn!(
+            "Ingest job {} was claimed again, outcome of attempt {} not recorded",
+            job.id, job.attempts
+        );
+    }
+    result?;
+
+    Ok(true)
+}
+
+/// Keep extending the lease of a running job until the task is aborted.
+fn spawn_lease_heartbeat(app_db: Arc<PgPool>, job_id: Uuid, lease_secs: i64) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = Duration::from_secs((lease_secs / 3).max(1) as u64);
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            match IngestJob::extend_lease(job_id, lease_secs, &*app_db).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(
+                        "Ingest job {} is no longer running, lease not extended",
+                        job_id
+                    );
+                    return;
+                }
+                Err(e) => warn!("Failed to extend lease of ingest job {}: {}", job_id, e),
+            }
+        }
+    })
+}
+
+/// Ingest the layer of a claimed job and set the job's outcome.
+async fn run_job(state: &AppState, job: &mut IngestJob) -> Result<()> {
+    let layer = Layer::get(job.layer_id, &*state.app_db).await?;
+
+    // The previous attempt died with its worker
+    if job.attempts > job.max_attempts {
+        let e = anyhow!("Ingest did not finish within {} attempts", job.max_attempts);
+        error!(
+            "Ingest job {} for layer {} failed permanently: {}",
+            job.id, layer.id, e
+        );
+        let stage = layer
+            .progress
+            .as_ref()
+            .map_or(IngestStage::Loading, |progress| progress.stage);
+        Layer::mark_failed(layer.id, &IngestError::new(stage, &e), &*state.app_db).await?;
+        job.status = JobStatus::Failed;
+        job.last_error = Some(e.to_string());
+        return Ok(());
+    }
+
+    if !Layer::mark_processing(layer.id, &*state.app_db).await? {
+        info!(
+            "Layer {} was cancelled, dropping ingest job {}",
+            layer.id, job.id
+        );
+        job.status = JobStatus::Failed;
+        job.last_error = Some("Layer was cancelled".to_string());
+        return Ok(());
+    }
+
+    let (progress, progress_handle) = ProgressTracker::start(
+        state.app_db.clone(),
+        layer.id,
+        state.ingest_config.progress_interval,
+    );
+
+    let result = async {
+        // Record the digest of the complete file before loading it
+        if layer.checksum.is_none() {
+            let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+            let checksum = ingest::file_checksum(&upload_file_path).await?;
+            Layer::update_checksum(layer.id, &checksum, &*state.app_db).await?;
+        }
+        let outcome = ingest::ingest_layer(state, &layer, &progress).await?;
+
+        if let Err(e) = record_loaded_layers(state, &layer, &outcome).await {
+            // Drop the loaded tables so the next attempt starts clean, an
+            // existing layer that was loaded into keeps its table
+            for loaded in &outcome.layers {
+                if Some(loaded.layer_id) != layer.ingest_options.target_layer_id {
+                    let _ = ingest::drop_layer_table(state, &loaded.table_name).await;
+                }
+            }
+            return Err(e);
+        }
+        Ok(outcome)
+    }
+    .await;
+
+    // Store the last progress before the outcome, a failure keeps the stage
+    // it was reached at
+    let stage = progress.current().stage;
+    drop(progress);
+    let _ = progress_handle.await;
+
+    match result {
+        Ok(outcome) => {
+            info!(
+                "Ingest job {} loaded {} features from {} source layers into layer {}",
+                job.id,
+                outcome.feature_count(),
+                outcome.layers.len(),
+                layer.id
+            );
+            job.status = JobStatus::Completed;
+            job.last_error = None;
+
+            // The data is in the database now, a failure to remove the file
+            // is left to the janitor
+            let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+            if let Err(e) = tokio::fs::remove_file(&upload_file_path).await
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!("Failed to remove upload file {:?}: {}", upload_file_path, e);
+            }
+        }
+        Err(e) => {
+            job.last_error = Some(e.to_string());
+            let ingest_error = IngestError::new(stage, &e);
+            // Problems with the file itself are not retried
+            if ingest_error.invalid_upload || job.attempts >= job.max_attempts {
+                error!(
+                    "Ingest job {} for layer {} failed permanently: {}",
+                    job.id, layer.id, e
+                );
+                job.status = JobStatus::Failed;
+                Layer::mark_failed(layer.id, &ingest_error, &*state.app_db).await?;
+            } else {
+                let backoff = retry_backoff(state.ingest_config.retry_backoff_secs, job.attempts);
+                warn!(
+                    "Ingest job {} for layer {} failed, retrying in {}s: {}",
+                    job.id, layer.id, backoff, e
+                );
+                job.status = JobStatus::Pending;
+                job.run_after = chrono::Utc::now() + chrono::Duration::seconds(backoff);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Exponential backoff before the next attempt, capped at
+/// `MAX_RETRY_BACKOFF_SECS`
+fn retry_backoff(base_secs: i64, attempts: i32) -> i64 {
+    let exponent = attempts.saturating_sub(1).max(0) as u32;
+    2i64.checked_pow(exponent)
+        .map_or(MAX_RETRY_BACKOFF_SECS, |factor| {
+            base_secs.saturating_mul(factor)
+        })
+        .min(MAX_RETRY_BACKOFF_SECS)
+}
+
+/// Mark the upload ready. Files with several source layers get a child
+/// layer per source layer, grouped under the upload. Uploads into an
+/// existing layer are marked merged and keep no table of their own, the
+/// version they created is recorded by the ingest.
+async fn record_loaded_layers(
+    state: &AppState,
+    layer: &Layer,
+    outcome: &IngestOutcome,
+) -> Result<()> {
+    let mut tx = state.app_db.begin().await?;
+
+    match outcome.layers.as_slice() {
+        [loaded] if loaded.layer_id == layer.id => {
+            Layer::mark_loaded(layer.id, loaded, &mut *tx).await?;
+        }
+        // The merge recorded the target's new version on the loading
+        // transaction, only the upload is left to finish
+        [loaded] if Some(loaded.layer_id) == layer.ingest_options.target_layer_id => {
+            Layer::mark_merged(layer.id, &mut *tx).await?;
+        }
+        loaded_layers => {
+            let now = chrono::Utc::now();
+            for loaded in loaded_layers {
+                let child = Layer {
+                    id: loaded.layer_id,
+                    status: LayerStatus::Ready,
+                    name: loaded.source_layer.clone(),
+                    upload_type: layer.upload_type.clone(),
+                    total_size: None,
+                    current_offset: 0,
+                    table_name: Some(loaded.table_name.clone()),
+                    checksum: None,
+                    expires_at: None,
+                    upload_concat: None,
+                    parent_id: Some(layer.id),
+                    source_layer: Some(loaded.source_layer.clone()),
+                    source_srid: loaded.source_srid,
+                    srid: Some(loaded.srid),
+                    bbox: loaded.bbox.clone(),
+                    geometry_type: loaded.geometry_type.clone(),
+                    feature_count: Some(loaded.feature_count as i64),
+                    fields: Some(loaded.fields.clone()),
+                    current_version: None,
+                    ingest_options: Default::default(),
+                    error: None,
+                    ingest_report: Some(loaded.report.clone()),
+                    progress: None,
+                    created_at: now,
+                    updated_at: now,
+                };
+                child.save(&mut *tx).await?;
+            }
+            Layer::mark_ready(layer.id, &mut *tx).await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_backoff_doubles_per_attempt() {
+        assert_eq!(retry_backoff(30, 1), 30);
+        assert_eq!(retry_backoff(30, 2), 60);
+        assert_eq!(retry_backoff(30, 4), 240);
+    }
+
+    #[test]
+    fn retry_backoff_is_capped() {
+        assert_eq!(retry_backoff(30, 20), MAX_RETRY_BACKOFF_SECS);
+        assert_eq!(retry_backoff(30, 64), MAX_RETRY_BACKOFF_SECS);
+        assert_eq!(retry_backoff(i64::MAX, 2), MAX_RETRY_BACKOFF_SECS);
+    }
+}
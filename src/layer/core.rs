@@ -1,6 +1,12 @@
+use crate::jobs::JobStatus;
+use crate::layer::LayerVersion;
+use crate::layer::ingest::{
+    ColumnSchema, IngestError, IngestOptions, IngestProgress, IngestReport, LoadedLayer,
+};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgRow;
+use sqlx::types::Json;
 use sqlx::{FromRow, Row};
 use strum_macros::{Display, EnumString};
 use uuid::Uuid;
@@ -11,6 +17,8 @@ pub enum LayerStatus {
     Uploading,
     Processing,
     Ready,
+    /// An upload whose data was merged into an existing layer
+    Merged,
     Error,
     Cancelled,
     Failed,
@@ -24,6 +32,24 @@ pub struct Layer {
     pub upload_type: Option<String>,
     pub total_size: Option<i64>,
     pub current_offset: i64,
+    pub table_name: Option<String>,
+    pub checksum: Option<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub upload_concat: Option<String>,
+    pub parent_id: Option<Uuid>,
+    pub source_layer: Option<String>,
+    pub source_srid: Option<i32>,
+    pub srid: Option<i32>,
+    pub bbox: Option<Vec<f64>>,
+    pub geometry_type: Option<String>,
+    pub feature_count: Option<i64>,
+    pub fields: Option<Vec<ColumnSchema>>,
+    /// Active version of layers that have been loaded into more than once
+    pub current_version: Option<i32>,
+    pub ingest_options: IngestOptions,
+    pub error: Option<IngestError>,
+    pub ingest_report: Option<IngestReport>,
+    pub progress: Option<IngestProgress>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
@@ -45,6 +71,34 @@ impl<'r> FromRow<'r, PgRow> for Layer {
             upload_type: row.try_get("upload_type")?,
             total_size: row.try_get::<Option<i64>, _>("total_size")?,
             current_offset: row.try_get::<i64, _>("current_offset")?,
+            table_name: row.try_get("table_name")?,
+            checksum: row.try_get("checksum")?,
+            expires_at: row.try_get("expires_at")?,
+            upload_concat: row.try_get("upload_concat")?,
+            parent_id: row.try_get("parent_id")?,
+            source_layer: row.try_get("source_layer")?,
+            source_srid: row.try_get("source_srid")?,
+            srid: row.try_get("srid")?,
+            bbox: row.try_get("bbox")?,
+            geometry_type: row.try_get("geometry_type")?,
+            feature_count: row.try_get("feature_count")?,
+            fields: row
+                .try_get::<Option<Json<Vec<ColumnSchema>>>, _>("fields")?
+                .map(|fields| fields.0),
+            current_version: row.try_get("current_version")?,
+            ingest_options: row
+                .try_get::<Option<Json<IngestOptions>>, _>("ingest_options")?
+                .map(|options| options.0)
+                .unwrap_or_default(),
+            error: row
+                .try_get::<Option<Json<IngestError>>, _>("error")?
+                .map(|error| error.0),
+            ingest_report: row
+                .try_get::<Option<Json<IngestReport>>, _>("ingest_report")?
+                .map(|report| report.0),
+            progress: row
+                .try_get::<Option<Json<IngestProgress>>, _>("progress")?
+                .map(|progress| progress.0),
             created_at: row.try_get("created_at")?,
             updated_at: row.try_get("updated_at")?,
         })
@@ -58,14 +112,31 @@ impl gridwalk_core::LayerCore for Layer {
     {
         async move {
             // Query to insert a new row
-            let query = "INSERT INTO gridwalk.layers (id, status, name, upload_type, total_size, current_offset, created_at, updated_at) \
-                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+            let query = "INSERT INTO gridwalk.layers (id, status, name, upload_type, total_size, current_offset, table_name, checksum, expires_at, upload_concat, parent_id, source_layer, source_srid, srid, bbox, geometry_type, feature_count, fields, current_version, ingest_options, error, ingest_report, progress, created_at, updated_at) \
+                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25) \
                          ON CONFLICT (id) DO UPDATE SET \
                          status = EXCLUDED.status, \
                          name = EXCLUDED.name, \
                          upload_type = EXCLUDED.upload_type, \
                          total_size = EXCLUDED.total_size, \
                          current_offset = EXCLUDED.current_offset, \
+                         table_name = EXCLUDED.table_name, \
+                         checksum = EXCLUDED.checksum, \
+                         expires_at = EXCLUDED.expires_at, \
+                         upload_concat = EXCLUDED.upload_concat, \
+                         parent_id = EXCLUDED.parent_id, \
+                         source_layer = EXCLUDED.source_layer, \
+                         source_srid = EXCLUDED.source_srid, \
+                         srid = EXCLUDED.srid, \
+                         bbox = EXCLUDED.bbox, \
+                         geometry_type = EXCLUDED.geometry_type, \
+                         feature_count = EXCLUDED.feature_count, \
+                         fields = EXCLUDED.fields, \
+                         current_version = EXCLUDED.current_version, \
+                         ingest_options = EXCLUDED.ingest_options, \
+                         error = EXCLUDED.error, \
+                         ingest_report = EXCLUDED.ingest_report, \
+                         progress = EXCLUDED.progress, \
                          updated_at = EXCLUDED.updated_at";
 
             sqlx::query(query)
@@ -75,6 +146,23 @@ impl gridwalk_core::LayerCore for Layer {
                 .bind(&self.upload_type)
                 .bind(self.total_size)
                 .bind(self.current_offset)
+                .bind(&self.table_name)
+                .bind(&self.checksum)
+                .bind(self.expires_at)
+                .bind(&self.upload_concat)
+                .bind(self.parent_id)
+                .bind(&self.source_layer)
+                .bind(self.source_srid)
+                .bind(self.srid)
+                .bind(&self.bbox)
+                .bind(&self.geometry_type)
+                .bind(self.feature_count)
+                .bind(self.fields.as_ref().map(Json))
+                .bind(self.current_version)
+                .bind(Json(&self.ingest_options))
+                .bind(self.error.as_ref().map(Json))
+                .bind(self.ingest_report.as_ref().map(Json))
+                .bind(self.progress.as_ref().map(Json))
                 .bind(self.created_at)
                 .bind(self.updated_at)
                 .execute(executor)
@@ -92,7 +180,10 @@ impl gridwalk_core::LayerCore for Layer {
         E: sqlx::Executor<'e, Database = sqlx::Postgres>,
     {
         async move {
-            let query = "SELECT * FROM gridwalk.layers ORDER BY created_at DESC LIMIT $1 OFFSET $2";
+            // Partial uploads only exist to be concatenated and are never listed
+            let query = "SELECT * FROM gridwalk.layers \
+                         WHERE upload_concat IS DISTINCT FROM 'partial' \
+                         ORDER BY created_at DESC LIMIT $1 OFFSET $2";
 
             let layers = sqlx::query_as::<_, Layer>(query)
                 .bind(limit as i64)
@@ -118,3 +209,389 @@ impl gridwalk_core::LayerCore for Layer {
         }
     }
 }
+
+impl Layer {
+    /// Whether this is a partial upload of a TUS concatenation
+    pub fn is_partial(&self) -> bool {
+        self.upload_concat.as_deref() == Some("partial")
+    }
+
+    /// Delete partial uploads once they are concatenated. Returns the number
+    /// of rows removed so callers can detect partials consumed concurrently.
+    pub async fn delete_partials<'e, E>(ids: &[Uuid], executor: E) -> Result<u64>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "DELETE FROM gridwalk.layers WHERE id = ANY($1) AND upload_concat = 'partial'";
+
+        let result = sqlx::query(query).bind(ids).execute(executor).await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Update only the status column, leaving upload progress untouched.
+    pub async fn update_status<'e, E>(id: Uuid, status: LayerStatus, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, updated_at = NOW() WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(status.to_string())
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Move a layer to `Processing` for an ingest attempt. Returns `false`
+    /// when the upload was cancelled while the job waited.
+    pub async fn mark_processing<'e, E>(id: Uuid, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, updated_at = NOW() \
+                     WHERE id = $1 AND status <> $3";
+
+        let result = sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Processing.to_string())
+            .bind(LayerStatus::Cancelled.to_string())
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Store the digest of the complete uploaded file.
+    pub async fn update_checksum<'e, E>(id: Uuid, checksum: &str, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET checksum = $2, updated_at = NOW() WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(checksum)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Replace the ingest options of a layer that is still uploading.
+    /// Returns `false` when the upload has already completed.
+    pub async fn update_ingest_options<'e, E>(
+        id: Uuid,
+        options: &IngestOptions,
+        executor: E,
+    ) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET ingest_options = $3, updated_at = NOW() \
+                     WHERE id = $1 AND status = $2";
+
+        let result = sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Uploading.to_string())
+            .bind(Json(options))
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Take the lock that serializes writers of an upload's file, held until
+    /// the session ends. Returns `false` when another request holds it.
+    pub async fn try_lock_upload<'e, E>(id: Uuid, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT pg_try_advisory_lock(hashtextextended($1::text, 0))";
+
+        let locked = sqlx::query_scalar::<_, bool>(query)
+            .bind(id)
+            .fetch_one(executor)
+            .await?;
+        Ok(locked)
+    }
+
+    /// Record the bytes a PATCH appended to an upload: its new offset, length
+    /// and expiry, and `Processing` once it is complete. Other columns are
+    /// left alone. Returns `false` when the upload is no longer uploading at
+    /// `start_offset`, e.g. it was cancelled or another request moved it on.
+    pub async fn record_chunk<'e, E>(&self, start_offset: i64, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $4, current_offset = $5, \
+                     total_size = $6, expires_at = $7, updated_at = $8 \
+                     WHERE id = $1 AND status = $2 AND current_offset = $3";
+
+        let result = sqlx::query(query)
+            .bind(self.id)
+            .bind(LayerStatus::Uploading.to_string())
+            .bind(start_offset)
+            .bind(self.status.to_string())
+            .bind(self.current_offset)
+            .bind(self.total_size)
+            .bind(self.expires_at)
+            .bind(self.updated_at)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Mark a layer as ready once a source layer has been loaded into its table.
+    pub async fn mark_loaded<'e, E>(id: Uuid, loaded: &LoadedLayer, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, table_name = $3, source_layer = $4, \
+                     source_srid = $5, srid = $6, bbox = $7, geometry_type = $8, feature_count = $9, \
+                     fields = $10, ingest_report = $11, current_version = $12, error = NULL, \
+                     updated_at = NOW() WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Ready.to_string())
+            .bind(&loaded.table_name)
+            .bind(&loaded.source_layer)
+            .bind(loaded.source_srid)
+            .bind(loaded.srid)
+            .bind(&loaded.bbox)
+            .bind(&loaded.geometry_type)
+            .bind(loaded.feature_count as i64)
+            .bind(Json(&loaded.fields))
+            .bind(Json(&loaded.report))
+            .bind(loaded.version)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a layer grouping the source layers of a multi-layer upload as
+    /// ready. It has no table of its own.
+    pub async fn mark_ready<'e, E>(id: Uuid, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, table_name = NULL, error = NULL, \
+                     updated_at = NOW() WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Ready.to_string())
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark an upload whose data was merged into an existing layer. The
+    /// data is served by that layer, the upload has no table of its own.
+    pub async fn mark_merged<'e, E>(id: Uuid, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, table_name = NULL, error = NULL, \
+                     updated_at = NOW() WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Merged.to_string())
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Store the progress of a running ingest.
+    pub async fn update_progress<'e, E>(
+        id: Uuid,
+        progress: &IngestProgress,
+        executor: E,
+    ) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET progress = $2, updated_at = NOW() WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(Json(progress))
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a layer as failed, keeping the reason for the user.
+    pub async fn mark_failed<'e, E>(id: Uuid, error: &IngestError, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, error = $3, updated_at = NOW() \
+                     WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Failed.to_string())
+            .bind(Json(error))
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Move a failed layer back to processing for another ingest, clearing
+    /// the previous error and progress. Returns `false` when the layer was
+    /// not failed anymore.
+    pub async fn mark_retrying<'e, E>(id: Uuid, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $3, error = NULL, progress = NULL, \
+                     updated_at = NOW() WHERE id = $1 AND status = $2";
+
+        let result = sqlx::query(query)
+            .bind(id)
+            .bind(LayerStatus::Failed.to_string())
+            .bind(LayerStatus::Processing.to_string())
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Make a stored version the active data of a layer.
+    pub async fn activate_version<'e, E>(
+        id: Uuid,
+        version: &LayerVersion,
+        executor: E,
+    ) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET current_version = $2, srid = $3, bbox = $4, \
+                     geometry_type = $5, feature_count = $6, fields = $7, updated_at = NOW() \
+                     WHERE id = $1";
+
+        sqlx::query(query)
+            .bind(id)
+            .bind(version.version)
+            .bind(version.srid)
+            .bind(&version.bbox)
+            .bind(&version.geometry_type)
+            .bind(version.feature_count)
+            .bind(version.fields.as_ref().map(Json))
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Read a layer and lock its row until the end of the transaction, so
+    /// changes to its versions apply one after the other.
+    pub async fn get_for_update<'e, E>(id: Uuid, executor: E) -> Result<Layer>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT * FROM gridwalk.layers WHERE id = $1 FOR UPDATE";
+
+        let layer = sqlx::query_as::<_, Layer>(query)
+            .bind(id)
+            .fetch_one(executor)
+            .await?;
+        Ok(layer)
+    }
+
+    /// Like `get_for_update`, without waiting for a lock held by another
+    /// transaction. Returns `None` while the row is locked.
+    pub async fn try_get_for_update<'e, E>(id: Uuid, executor: E) -> Result<Option<Layer>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT * FROM gridwalk.layers WHERE id = $1 FOR UPDATE NOWAIT";
+
+        match sqlx::query_as::<_, Layer>(query)
+            .bind(id)
+            .fetch_one(executor)
+            .await
+        {
+            Ok(layer) => Ok(Some(layer)),
+            // lock_not_available
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("55P03") => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether an upload into this layer is being ingested, or has an ingest
+    /// job queued or waiting to be retried.
+    pub async fn has_pending_merge<'e, E>(id: Uuid, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT EXISTS (SELECT 1 FROM gridwalk.layers AS upload \
+                     WHERE upload.ingest_options->>'target_layer_id' = $1 AND (upload.status = $2 \
+                     OR EXISTS (SELECT 1 FROM gridwalk.ingest_jobs AS job \
+                     WHERE job.layer_id = upload.id AND job.status IN ($3, $4))))";
+
+        let pending = sqlx::query_scalar::<_, bool>(query)
+            .bind(id.to_string())
+            .bind(LayerStatus::Processing.to_string())
+            .bind(JobStatus::Pending.to_string())
+            .bind(JobStatus::Running.to_string())
+            .fetch_one(executor)
+            .await?;
+        Ok(pending)
+    }
+
+    /// Layers created from the source layers of a multi-layer upload.
+    pub async fn children<'e, E>(parent_id: Uuid, executor: E) -> Result<Vec<Layer>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT * FROM gridwalk.layers WHERE parent_id = $1 ORDER BY created_at";
+
+        let layers = sqlx::query_as::<_, Layer>(query)
+            .bind(parent_id)
+            .fetch_all(executor)
+            .await?;
+        Ok(layers)
+    }
+
+    /// Cancel every upload whose expiry has passed, returning their ids.
+    pub async fn cancel_expired<'e, E>(executor: E) -> Result<Vec<Uuid>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "UPDATE gridwalk.layers SET status = $2, updated_at = NOW() \
+                     WHERE status = $1 AND expires_at < NOW() \
+                     RETURNING id";
+
+        let ids = sqlx::query_scalar::<_, Uuid>(query)
+            .bind(LayerStatus::Uploading.to_string())
+            .bind(LayerStatus::Cancelled.to_string())
+            .fetch_all(executor)
+            .await?;
+        Ok(ids)
+    }
+
+    /// Of the given layers, those whose upload file is no longer needed:
+    /// ready and merged layers, and failed layers not retried for
+    /// `retention_secs`.
+    pub async fn finished_uploads<'e, E>(
+        ids: &[Uuid],
+        retention_secs: i64,
+        executor: E,
+    ) -> Result<Vec<Uuid>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT id FROM gridwalk.layers WHERE id = ANY($1) \
+                     AND (status IN ($2, $3) OR (status = $4 \
+                     AND updated_at < NOW() - make_interval(secs => $5)))";
+
+        let ids = sqlx::query_scalar::<_, Uuid>(query)
+            .bind(ids)
+            .bind(LayerStatus::Ready.to_string())
+            .bind(LayerStatus::Merged.to_string())
+            .bind(LayerStatus::Failed.to_string())
+            .bind(retention_secs as f64)
+            .fetch_all(executor)
+            .await?;
+        Ok(ids)
+    }
+}
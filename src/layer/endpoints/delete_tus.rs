@@ -0,0 +1,168 @@
+use super::layer_lookup_error;
+use crate::config::AppState;
+use crate::layer::tus::{TUS_VERSION, check_tus_resumable};
+use crate::layer::{Layer, LayerStatus, LayerVersion, ingest};
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::{HeaderMap, StatusCode, header::HeaderValue},
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::fs;
+use tracing::info;
+use uuid::Uuid;
+
+// DELETE (using TUS termination extension) function to abort or remove an upload
+#[axum::debug_handler]
+pub async fn delete_tus(
+    RequestPath(layer_id): RequestPath<Uuid>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    // Validate TUS-Resumable header
+    check_tus_resumable(&headers)?;
+
+    // The row stays locked until the layer is cancelled, so no upload can be
+    // queued into it or merged into it in between. A merge into the layer
+    // holds the lock while it runs.
+    let mut tx = state.app_db.begin().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Database error: {}", e)})),
+        )
+    })?;
+    let Some(layer) = Layer::try_get_for_update(layer_id, &mut *tx)
+        .await
+        .map_err(layer_lookup_error)?
+    else {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "The layer is being changed by another request"})),
+        ));
+    };
+
+    if layer.status == LayerStatus::Processing {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Layer is being processed and cannot be deleted"})),
+        ));
+    }
+
+    // An upload into this layer would load into tables dropped below
+    let pending_merge = Layer::has_pending_merge(layer.id, &mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Database error: {}", e)})),
+            )
+        })?;
+    if pending_merge {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "An upload into this layer is being processed"})),
+        ));
+    }
+
+    // The layer is cancelled before its data is removed so no PATCH or ingest
+    // can pick it up again. A cancelled layer still runs the cleanup below,
+    // so a DELETE that failed half way can be repeated.
+    let already_cancelled = layer.status == LayerStatus::Cancelled;
+    if !already_cancelled {
+        Layer::update_status(layer.id, LayerStatus::Cancelled, &mut *tx)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Failed to cancel layer: {}", e)})),
+                )
+            })?;
+    }
+    tx.commit().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to cancel layer: {}", e)})),
+        )
+    })?;
+
+    // Remove the uploaded file, it may already be gone
+    let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+    if let Err(e) = fs::remove_file(&upload_file_path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to remove upload file: {}", e)})),
+        ));
+    }
+
+    // Drop the loaded data, only layers that finished loading have a table
+    if let Some(table_name) = &layer.table_name {
+        ingest::drop_layer_table(&state, table_name)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Failed to drop layer table: {}", e)})),
+                )
+            })?;
+    }
+
+    // Versions of a layer that was merged into went with its tables
+    LayerVersion::delete_all(layer.id, &*state.app_db)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to remove layer versions: {}", e)})),
+            )
+        })?;
+
+    // Uploads with several source layers keep their data in child layers
+    let children = Layer::children(layer.id, &*state.app_db)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to fetch child layers: {}", e)})),
+            )
+        })?;
+
+    for child in children {
+        if let Some(table_name) = &child.table_name {
+            ingest::drop_layer_table(&state, table_name)
+                .await
+                .map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        axum::Json(json!({"error": format!("Failed to drop layer table: {}", e)})),
+                    )
+                })?;
+        }
+        if child.status != LayerStatus::Cancelled {
+            Layer::update_status(child.id, LayerStatus::Cancelled, &*state.app_db)
+                .await
+                .map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        axum::Json(json!({"error": format!("Failed to cancel layer: {}", e)})),
+                    )
+                })?;
+        }
+    }
+
+    if already_cancelled {
+        return Err((
+            StatusCode::GONE,
+            axum::Json(json!({"error": "Upload has already been cancelled"})),
+        ));
+    }
+
+    info!("Cancelled layer {}", layer.id);
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+
+    Ok((StatusCode::NO_CONTENT, response_headers))
+}
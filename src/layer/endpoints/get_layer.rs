@@ -0,0 +1,20 @@
+use super::fetch_layer;
+use crate::config::AppState;
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET endpoint to retrieve a single layer, including its ingest progress
+#[axum::debug_handler]
+pub async fn get_layer(
+    RequestPath(layer_id): RequestPath<Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let layer = fetch_layer(&state, layer_id).await?;
+
+    Ok(axum::Json(layer))
+}
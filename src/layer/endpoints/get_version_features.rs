@@ -0,0 +1,49 @@
+use super::fetch_version;
+use crate::config::AppState;
+use crate::layer::ingest;
+use axum::{
+    extract::{Path as RequestPath, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Most features returned in one page
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct FeaturesQuery {
+    #[serde(default = "default_limit")]
+    limit: u64,
+    #[serde(default)]
+    offset: u64,
+}
+
+fn default_limit() -> u64 {
+    100
+}
+
+/// GET endpoint returning the features of one version of a layer as GeoJSON
+#[axum::debug_handler]
+pub async fn get_version_features(
+    RequestPath((layer_id, version)): RequestPath<(Uuid, i32)>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeaturesQuery>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let version = fetch_version(&state, layer_id, version).await?;
+
+    let features =
+        ingest::version_features(&state, &version, query.limit.min(MAX_LIMIT), query.offset)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Failed to fetch features: {}", e)})),
+                )
+            })?;
+
+    Ok(axum::Json(features))
+}
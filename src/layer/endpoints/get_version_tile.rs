@@ -0,0 +1,55 @@
+use super::fetch_version;
+use crate::config::AppState;
+use crate::layer::ingest;
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::{HeaderMap, StatusCode, header::HeaderValue},
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET endpoint to retrieve a map tile of one version of a layer in MVT
+/// (Mapbox Vector Tile) format
+#[axum::debug_handler]
+pub async fn get_version_tile(
+    RequestPath((layer_id, version, z, x, y)): RequestPath<(Uuid, i32, u32, u32, u32)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    if z > 30 || x >= 1 << z || y >= 1 << z {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": "Tile coordinates are out of range"})),
+        ));
+    }
+
+    let version = fetch_version(&state, layer_id, version).await?;
+
+    let tile_data = ingest::version_tile(&state, &version, z, x, y)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to get tile: {}", e)})),
+            )
+        })?;
+
+    if tile_data.is_empty() {
+        return Ok((StatusCode::NO_CONTENT, HeaderMap::new(), Vec::new()));
+    }
+
+    // Versions never change, so their tiles can be cached for longer
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "content-type",
+        HeaderValue::from_static("application/vnd.mapbox-vector-tile"),
+    );
+    headers.insert(
+        "cache-control",
+        HeaderValue::from_static("public, max-age=86400"),
+    );
+    headers.insert("access-control-allow-origin", HeaderValue::from_static("*"));
+
+    Ok((StatusCode::OK, headers, tile_data))
+}
@@ -0,0 +1,34 @@
+use super::fetch_versioned_layer;
+use crate::config::AppState;
+use crate::layer::LayerVersion;
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET endpoint listing the versions of a layer, newest first
+#[axum::debug_handler]
+pub async fn get_versions(
+    RequestPath(layer_id): RequestPath<Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let layer = fetch_versioned_layer(&state, layer_id).await?;
+
+    let versions = LayerVersion::list(layer.id, &*state.app_db)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to fetch versions: {}", e)})),
+            )
+        })?;
+
+    Ok(axum::Json(json!({
+        "current_version": layer.current_version,
+        "versions": versions
+    })))
+}
@@ -0,0 +1,88 @@
+use super::fetch_layer;
+use crate::config::AppState;
+use crate::layer::LayerStatus;
+use crate::layer::tus::{TUS_VERSION, upload_expires_header};
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{CACHE_CONTROL, HeaderValue},
+    },
+    response::IntoResponse,
+};
+use base64::prelude::*;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+// HEAD (using TUS protocol) function to report the upload progress of a layer
+#[axum::debug_handler]
+pub async fn head_tus(
+    RequestPath(layer_id): RequestPath<Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let layer = fetch_layer(&state, layer_id).await?;
+
+    // Terminated uploads are gone for good
+    if layer.status == LayerStatus::Cancelled {
+        return Err((
+            StatusCode::GONE,
+            axum::Json(json!({"error": "Upload has been cancelled"})),
+        ));
+    }
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+    response_headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response_headers.insert("upload-offset", HeaderValue::from(layer.current_offset));
+
+    match layer.total_size {
+        Some(total_size) => {
+            response_headers.insert("upload-length", HeaderValue::from(total_size));
+        }
+        None => {
+            response_headers.insert("upload-defer-length", HeaderValue::from_static("1"));
+        }
+    }
+
+    if layer.status == LayerStatus::Uploading
+        && let Some(expires_at) = layer.expires_at
+    {
+        response_headers.insert("upload-expires", upload_expires_header(expires_at));
+    }
+
+    if let Some(upload_concat) = &layer.upload_concat {
+        response_headers.insert(
+            "upload-concat",
+            HeaderValue::from_str(upload_concat).map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": "Failed to create upload-concat header"})),
+                )
+            })?,
+        );
+    }
+
+    // Re-encode the metadata the client sent when creating the upload
+    let mut metadata = vec![format!("name {}", BASE64_STANDARD.encode(&layer.name))];
+    if let Some(upload_type) = &layer.upload_type {
+        metadata.push(format!(
+            "upload_type {}",
+            BASE64_STANDARD.encode(upload_type)
+        ));
+    }
+    for (key, value) in layer.ingest_options.metadata() {
+        metadata.push(format!("{} {}", key, BASE64_STANDARD.encode(value)));
+    }
+    response_headers.insert(
+        "upload-metadata",
+        HeaderValue::from_str(&metadata.join(",")).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": "Failed to create upload-metadata header"})),
+            )
+        })?,
+    );
+
+    Ok((StatusCode::OK, response_headers))
+}
@@ -1,9 +1,99 @@
+mod delete_tus;
+mod get_layer;
 mod get_layers;
+mod get_version_features;
+mod get_version_tile;
+mod get_versions;
+mod head_tus;
+mod options_tus;
 mod patch_tus;
 mod post_tus;
+mod put_field_mapping;
+mod retry_layer;
+mod rollback_layer;
 mod tiles;
+mod validate_upload;
 
+pub use delete_tus::*;
+pub use get_layer::*;
 pub use get_layers::*;
+pub use get_version_features::*;
+pub use get_version_tile::*;
+pub use get_versions::*;
+pub use head_tus::*;
+pub use options_tus::*;
 pub use patch_tus::*;
 pub use post_tus::*;
+pub use put_field_mapping::*;
+pub use retry_layer::*;
+pub use rollback_layer::*;
 pub use tiles::*;
+pub use validate_upload::*;
+
+use crate::config::AppState;
+use crate::layer::{Layer, LayerStatus, LayerVersion};
+use axum::http::StatusCode;
+use gridwalk_core::LayerCore;
+use serde_json::json;
+use uuid::Uuid;
+
+/// Fetch the layer a request is about, answering 404 when it does not exist
+pub async fn fetch_layer(
+    state: &AppState,
+    layer_id: Uuid,
+) -> Result<Layer, (StatusCode, axum::Json<serde_json::Value>)> {
+    Layer::get(layer_id, &*state.app_db)
+        .await
+        .map_err(layer_lookup_error)
+}
+
+/// Fetch a layer whose versions a request reads, answering 410 once the
+/// layer was cancelled and its data dropped
+pub async fn fetch_versioned_layer(
+    state: &AppState,
+    layer_id: Uuid,
+) -> Result<Layer, (StatusCode, axum::Json<serde_json::Value>)> {
+    let layer = fetch_layer(state, layer_id).await?;
+    if layer.status == LayerStatus::Cancelled {
+        return Err((
+            StatusCode::GONE,
+            axum::Json(json!({"error": "Layer has been cancelled"})),
+        ));
+    }
+    Ok(layer)
+}
+
+/// Fetch one version of a layer, answering 404 when it does not exist
+pub async fn fetch_version(
+    state: &AppState,
+    layer_id: Uuid,
+    version: i32,
+) -> Result<LayerVersion, (StatusCode, axum::Json<serde_json::Value>)> {
+    let layer = fetch_versioned_layer(state, layer_id).await?;
+    LayerVersion::get(layer.id, version, &*state.app_db)
+        .await
+        .map_err(|e| match e.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => (
+                StatusCode::NOT_FOUND,
+                axum::Json(json!({"error": "Layer version not found"})),
+            ),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Database error: {}", e)})),
+            ),
+        })
+}
+
+/// Response for a failed layer lookup, 404 when the layer does not exist
+pub fn layer_lookup_error(e: anyhow::Error) -> (StatusCode, axum::Json<serde_json::Value>) {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::RowNotFound) => (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({"error": "Layer not found"})),
+        ),
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Database error: {}", e)})),
+        ),
+    }
+}
@@ -0,0 +1,40 @@
+use crate::config::AppState;
+use crate::layer::tus::{TUS_CHECKSUM_ALGORITHMS, TUS_EXTENSIONS, TUS_VERSION};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode, header::HeaderValue},
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+// OPTIONS (using TUS protocol) function to advertise server capabilities
+#[axum::debug_handler]
+pub async fn options_tus(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+    response_headers.insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+    response_headers.insert(
+        "tus-extension",
+        HeaderValue::from_str(&TUS_EXTENSIONS.join(",")).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": "Failed to create tus-extension header"})),
+            )
+        })?,
+    );
+    response_headers.insert(
+        "tus-checksum-algorithm",
+        HeaderValue::from_str(&TUS_CHECKSUM_ALGORITHMS.join(",")).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": "Failed to create tus-checksum-algorithm header"})),
+            )
+        })?,
+    );
+    response_headers.insert("tus-max-size", HeaderValue::from(state.max_upload_size));
+
+    Ok((StatusCode::NO_CONTENT, response_headers))
+}
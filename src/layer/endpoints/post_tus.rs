@@ -1,6 +1,11 @@
 use crate::config::AppState;
-use crate::layer::{Layer, LayerStatus};
+use crate::layer::tus::{
+    TUS_VERSION, UploadConcat, UploadMetadata, check_tus_resumable, parse_upload_checksum,
+    parse_upload_concat, parse_upload_metadata, upload_expires_header,
+};
+use crate::layer::{Layer, LayerStatus, upload};
 use axum::{
+    body::Body,
     extract::State,
     http::{
         HeaderMap, StatusCode,
@@ -8,11 +13,11 @@ use axum::{
     },
     response::IntoResponse,
 };
-use base64::prelude::*;
 use gridwalk_core::LayerCore;
 use serde_json::json;
 use std::sync::Arc;
 use tokio::fs;
+use tracing::warn;
 use uuid::Uuid;
 
 // POST (using TUS protocol) function to create a new layer
@@ -20,20 +25,36 @@ use uuid::Uuid;
 pub async fn post_tus(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
+    body: Body,
 ) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
     // Validate TUS-Resumable header
-    let _tus_version = headers
-        .get("tus-resumable")
+    check_tus_resumable(&headers)?;
+
+    // With creation-with-upload the body carries the first chunk of the file
+    let has_initial_chunk = headers
+        .get("content-type")
         .and_then(|v| v.to_str().ok())
-        .ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                axum::Json(json!({"error": "Missing Tus-Resumable header"})),
-            )
-        })?;
+        .is_some_and(|content_type| content_type == "application/offset+octet-stream");
+
+    // Extract the optional Upload-Checksum header for the initial chunk
+    let upload_checksum = parse_upload_checksum(&headers)?;
+
+    // Extract the optional Upload-Concat header
+    let upload_concat = parse_upload_concat(&headers)?;
+    let is_final = matches!(upload_concat, Some(UploadConcat::Final(_)));
+
+    // Final uploads get their data and length from the partial uploads
+    if is_final && (has_initial_chunk || headers.contains_key("upload-length")) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": "Final uploads must not include Upload-Length or a body"})),
+        ));
+    }
 
     // Extract Upload-Length or Upload-Defer-Length header
-    let total_size: Option<i64> = if let Some(length_header) = headers.get("upload-length") {
+    let total_size: Option<i64> = if is_final {
+        None
+    } else if let Some(length_header) = headers.get("upload-length") {
         Some(
             length_header
                 .to_str()
@@ -75,67 +96,65 @@ pub async fn post_tus(
         ));
     };
 
-    // Parse Upload-Metadata header
-    let metadata_header = headers.get("upload-metadata").ok_or_else(|| {
-        (
+    if total_size.is_some_and(|total_size| total_size < 0) {
+        return Err((
             StatusCode::BAD_REQUEST,
-            axum::Json(json!({"error": "Missing Upload-Metadata header"})),
-        )
-    })?;
+            axum::Json(json!({"error": "Upload-Length must not be negative"})),
+        ));
+    }
 
-    let metadata_str = metadata_header.to_str().map_err(|_| {
-        (
+    if total_size.is_some_and(|total_size| total_size > state.max_upload_size) {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            axum::Json(json!({
+                "error": "Upload-Length exceeds the maximum upload size",
+                "max_size": state.max_upload_size
+            })),
+        ));
+    }
+
+    // Partial uploads are never ingested on their own, so metadata is optional
+    let is_partial = matches!(upload_concat, Some(UploadConcat::Partial));
+
+    let Some(UploadMetadata {
+        mut name,
+        upload_type,
+        ingest_options,
+    }) = parse_upload_metadata(&headers)?.or_else(|| is_partial.then(UploadMetadata::default))
+    else {
+        return Err((
             StatusCode::BAD_REQUEST,
-            axum::Json(json!({"error": "Invalid Upload-Metadata header"})),
-        )
-    })?;
+            axum::Json(json!({"error": "Missing Upload-Metadata header"})),
+        ));
+    };
 
-    let mut name: Option<String> = None;
-    let mut upload_type: Option<String> = None;
-
-    for pair in metadata_str.split(',') {
-        let parts: Vec<&str> = pair.trim().splitn(2, ' ').collect();
-        if parts.len() == 2 {
-            let key = parts[0];
-            if let Ok(decoded_value) = BASE64_STANDARD.decode(parts[1]) {
-                if let Ok(value) = String::from_utf8(decoded_value) {
-                    match key {
-                        "name" => {
-                            let trimmed_name = value.trim().to_string();
-                            if trimmed_name
-                                .chars()
-                                .all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-')
-                            {
-                                name = Some(trimmed_name);
-                            } else {
-                                return Err((
-                                    StatusCode::BAD_REQUEST,
-                                    axum::Json(
-                                        json!({"error": "Name can only contain alphanumeric characters, spaces, hyphens, and underscores"}),
-                                    ),
-                                ));
-                            }
-                        }
-                        "upload_type" => {
-                            let trimmed_type = value.trim().to_string();
-                            if trimmed_type.chars().all(|c| c.is_alphabetic()) {
-                                upload_type = Some(trimmed_type);
-                            } else {
-                                return Err((
-                                    StatusCode::BAD_REQUEST,
-                                    axum::Json(
-                                        json!({"error": "Upload type can only contain letters"}),
-                                    ),
-                                ));
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
+    // Uploads into an existing layer need a target that holds data of its own
+    if let Some(target_layer_id) = ingest_options.target_layer_id {
+        let target = Layer::get(target_layer_id, &*state.app_db)
+            .await
+            .map_err(|e| match e.downcast_ref::<sqlx::Error>() {
+                Some(sqlx::Error::RowNotFound) => (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(json!({"error": "Target layer not found"})),
+                ),
+                _ => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Database error: {}", e)})),
+                ),
+            })?;
+
+        if target.status != LayerStatus::Ready || target.table_name.is_none() {
+            return Err((
+                StatusCode::CONFLICT,
+                axum::Json(json!({"error": "Target layer must be ready and hold its own data"})),
+            ));
         }
     }
 
+    if is_partial {
+        name.get_or_insert_with(|| "partial upload".to_string());
+    }
+
     let name = name.ok_or_else(|| {
         (
             StatusCode::BAD_REQUEST,
@@ -143,43 +162,103 @@ pub async fn post_tus(
         )
     })?;
 
-    let upload_type = upload_type.ok_or_else(|| {
-        (
+    if upload_type.is_none() && !is_partial {
+        return Err((
             StatusCode::BAD_REQUEST,
             axum::Json(json!({"error": "Missing 'upload_type' in Upload-Metadata"})),
-        )
-    })?;
+        ));
+    }
 
-    let layer = Layer {
+    let now = chrono::Utc::now();
+    let mut layer = Layer {
         id: Uuid::new_v4(),
         status: LayerStatus::Uploading,
         name,
-        upload_type: Some(upload_type),
+        upload_type,
         total_size,
         current_offset: 0,
-        created_at: chrono::Utc::now(),
-        updated_at: chrono::Utc::now(),
+        table_name: None,
+        checksum: None,
+        expires_at: Some(now + chrono::Duration::seconds(state.upload_expiry_secs)),
+        upload_concat: headers
+            .get("upload-concat")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string()),
+        parent_id: None,
+        source_layer: None,
+        source_srid: None,
+        srid: None,
+        bbox: None,
+        geometry_type: None,
+        feature_count: None,
+        fields: None,
+        current_version: None,
+        ingest_options,
+        error: None,
+        ingest_report: None,
+        progress: None,
+        created_at: now,
+        updated_at: now,
     };
 
-    // Create empty file for TUS upload
-    let upload_file_path = state.temp_data_path.join(layer.id.to_string());
-    println!("Creating upload file at {:?}", upload_file_path);
-    fs::File::create(&upload_file_path).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            axum::Json(json!({"error": format!("Failed to create upload file: {}", e)})),
-        )
-    })?;
+    let upload_complete = if let Some(UploadConcat::Final(partial_ids)) = &upload_concat {
+        // Join the partial uploads and queue the result for ingest
+        upload::concatenate_partials(&state, &mut layer, partial_ids).await?;
+        true
+    } else {
+        // Create empty file for TUS upload
+        let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+        println!("Creating upload file at {:?}", upload_file_path);
+        fs::File::create(&upload_file_path).await.map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to create upload file: {}", e)})),
+            )
+        })?;
 
-    layer.save(&*state.app_db).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            axum::Json(json!({"error": format!("Failed to save layer: {}", e)})),
-        )
-    })?;
+        layer.save(&*state.app_db).await.map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to save layer: {}", e)})),
+            )
+        })?;
+
+        // Write the initial chunk, which queues ingest straight away if it
+        // already completes the file
+        if has_initial_chunk {
+            match upload::append_body(&state, &mut layer, body, upload_checksum.as_ref()).await {
+                Ok(upload_complete) => upload_complete,
+                Err((status, error)) => {
+                    // The upload exists either way, the client resumes it from
+                    // the offset that was actually stored
+                    warn!(
+                        "Initial chunk for layer {} failed with {}: {}",
+                        layer.id, status, error.0
+                    );
+                    let stored = Layer::get(layer.id, &*state.app_db).await.map_err(|e| {
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            axum::Json(json!({"error": format!("Database error: {}", e)})),
+                        )
+                    })?;
+                    layer.current_offset = stored.current_offset;
+                    layer.expires_at = stored.expires_at;
+                    false
+                }
+            }
+        } else {
+            false
+        }
+    };
 
     let mut response_headers = HeaderMap::new();
-    response_headers.insert("tus-resumable", HeaderValue::from_static("1.0.0"));
+    response_headers.insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+    if has_initial_chunk {
+        response_headers.insert("upload-offset", HeaderValue::from(layer.current_offset));
+    }
+    if !upload_complete && let Some(expires_at) = layer.expires_at {
+        response_headers.insert("upload-expires", upload_expires_header(expires_at));
+    }
     response_headers.insert(
         LOCATION,
         HeaderValue::from_str(&format!("/layers/{}", layer.id)).map_err(|_| {
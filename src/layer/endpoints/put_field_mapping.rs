@@ -0,0 +1,60 @@
+use super::fetch_layer;
+use crate::config::AppState;
+use crate::layer::ingest::FieldMapping;
+use crate::layer::{Layer, LayerStatus};
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// PUT endpoint attaching a field mapping to an upload before its final
+/// chunk. The body is the mapping document, an empty object removes it.
+#[axum::debug_handler]
+pub async fn put_field_mapping(
+    RequestPath(layer_id): RequestPath<Uuid>,
+    State(state): State<Arc<AppState>>,
+    body: String,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let field_mapping = FieldMapping::parse(&body).map_err(|message| {
+        (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": message})),
+        )
+    })?;
+
+    let mut layer = fetch_layer(&state, layer_id).await?;
+
+    // Partial uploads are never ingested, the final upload carries the options
+    if layer.status != LayerStatus::Uploading || layer.is_partial() {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(
+                json!({"error": "Field mappings can only be attached to uploads in progress"}),
+            ),
+        ));
+    }
+
+    layer.ingest_options.field_mapping = (!field_mapping.0.is_empty()).then_some(field_mapping);
+
+    // The final chunk may have landed since the layer was read
+    let updated = Layer::update_ingest_options(layer.id, &layer.ingest_options, &*state.app_db)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to update layer: {}", e)})),
+            )
+        })?;
+    if !updated {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Upload has already completed"})),
+        ));
+    }
+
+    Ok(axum::Json(layer.ingest_options))
+}
@@ -0,0 +1,90 @@
+use super::fetch_layer;
+use crate::config::AppState;
+use crate::jobs::IngestJob;
+use crate::layer::{Layer, LayerStatus};
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::fs;
+use tracing::info;
+use uuid::Uuid;
+
+/// POST endpoint to ingest a failed layer again from its uploaded file
+#[axum::debug_handler]
+pub async fn retry_layer(
+    RequestPath(layer_id): RequestPath<Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    let layer = fetch_layer(&state, layer_id).await?;
+
+    if layer.status != LayerStatus::Failed {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Only failed layers can be retried"})),
+        ));
+    }
+
+    // The upload is read again from the file it was first ingested from
+    let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+    let file_exists = fs::try_exists(&upload_file_path).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to check upload file: {}", e)})),
+        )
+    })?;
+    if !file_exists {
+        return Err((
+            StatusCode::GONE,
+            axum::Json(json!({"error": "Uploaded file is no longer available"})),
+        ));
+    }
+
+    let mut tx = state.app_db.begin().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to start transaction: {}", e)})),
+        )
+    })?;
+
+    // Only queue one retry when several requests race
+    let retrying = Layer::mark_retrying(layer.id, &mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to update layer: {}", e)})),
+            )
+        })?;
+    if !retrying {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Layer status changed, please retry"})),
+        ));
+    }
+
+    let job = IngestJob::new(layer.id, state.ingest_config.max_attempts);
+    job.save(&mut *tx).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to queue ingest job: {}", e)})),
+        )
+    })?;
+
+    tx.commit().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to commit transaction: {}", e)})),
+        )
+    })?;
+
+    info!("Queued ingest job {} to retry layer {}", job.id, layer.id);
+
+    Ok((
+        StatusCode::ACCEPTED,
+        axum::Json(json!({"layer_id": layer.id, "job_id": job.id})),
+    ))
+}
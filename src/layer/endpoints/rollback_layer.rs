@@ -0,0 +1,116 @@
+use super::layer_lookup_error;
+use crate::config::AppState;
+use crate::layer::{Layer, LayerStatus, LayerVersion, ingest};
+use axum::{
+    extract::{Path as RequestPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use gridwalk_core::LayerCore;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+/// POST endpoint making an earlier version the active data of a layer
+#[axum::debug_handler]
+pub async fn rollback_layer(
+    RequestPath((layer_id, version)): RequestPath<(Uuid, i32)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    // The layer's row stays locked on the app database until the rollback is
+    // recorded, so a merge waits for it and builds on the restored version.
+    // A merge holds the lock for its whole ingest, which is not waited for.
+    let mut tx = state.app_db.begin().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Database error: {}", e)})),
+        )
+    })?;
+    let Some(layer) = Layer::try_get_for_update(layer_id, &mut *tx)
+        .await
+        .map_err(layer_lookup_error)?
+    else {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "The layer is being changed by another request"})),
+        ));
+    };
+
+    let (LayerStatus::Ready, Some(table_name), Some(_)) =
+        (&layer.status, &layer.table_name, layer.current_version)
+    else {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Only ready layers with versions can be rolled back"})),
+        ));
+    };
+
+    // A merge in progress builds on the version that was active when it started
+    let pending_merge = Layer::has_pending_merge(layer.id, &mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Database error: {}", e)})),
+            )
+        })?;
+    if pending_merge {
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "An upload into this layer is being processed"})),
+        ));
+    }
+
+    let version = LayerVersion::get(layer.id, version, &mut *tx)
+        .await
+        .map_err(|e| match e.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => (
+                StatusCode::NOT_FOUND,
+                axum::Json(json!({"error": "Layer version not found"})),
+            ),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Database error: {}", e)})),
+            ),
+        })?;
+
+    // The data side switches first, the layer's record follows under the lock
+    ingest::activate_version(&state, table_name, &version)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to activate version: {}", e)})),
+            )
+        })?;
+
+    Layer::activate_version(layer.id, &version, &mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to update layer: {}", e)})),
+            )
+        })?;
+
+    tx.commit().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to commit rollback: {}", e)})),
+        )
+    })?;
+
+    info!(
+        "Rolled layer {} back to version {}",
+        layer.id, version.version
+    );
+
+    let layer = Layer::get(layer.id, &*state.app_db).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to fetch layer: {}", e)})),
+        )
+    })?;
+    Ok(axum::Json(layer))
+}
@@ -1,51 +1,55 @@
+use super::fetch_layer;
 use crate::config::AppState;
+use crate::layer::{Layer, LayerStatus, ingest};
 use axum::{
     extract::{Path as RequestPath, State},
     http::{HeaderMap, StatusCode, header::HeaderValue},
     response::IntoResponse,
 };
-use gridwalk_core::VectorConnector;
 use serde_json::json;
+use std::f64::consts::PI;
 use std::sync::Arc;
 use uuid::Uuid;
 
+/// Fraction of a tile's width added around it, matching the MVT buffer
+/// so features just outside the tile still count
+const TILE_BUFFER: f64 = 64.0 / 4096.0;
+
 /// GET endpoint to retrieve a map tile in MVT (Mapbox Vector Tile) format
 #[axum::debug_handler]
 pub async fn get_tile(
     RequestPath((layer_id, z, x, y)): RequestPath<(Uuid, u32, u32, u32)>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
-    // Get the vector connector from state
-    let vector_connector = if let Some(vector_connector) = state.connection.as_vector() {
-        vector_connector
+    // Skip tiles entirely outside the layer's extent
+    let layer = fetch_layer(&state, layer_id).await?;
+
+    if let Some(bbox) = &layer.bbox
+        && !tile_intersects(bbox, z, x, y)
+    {
+        return Ok((StatusCode::NO_CONTENT, HeaderMap::new(), Vec::new()));
+    }
+
+    // Rendered from the table the ingest created, whose layout this crate
+    // owns. Layers loaded before the table name and SRID were recorded are
+    // still served by the PostGIS connector. Uploads that are still loading,
+    // and groups of child layers, have no data of their own.
+    let tile_data = if layer.table_name.is_some() && layer.srid.is_some() {
+        ingest::layer_tile(&state, &layer, z, x, y).await
+    } else if layer.status == LayerStatus::Ready && !has_children(&state, layer.id).await? {
+        ingest::legacy_tile(&state, layer.id, z, x, y).await
     } else {
         return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            axum::Json(json!({"error": "Connection is not a vector connector"})),
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({"error": "Layer has no data to render"})),
         ));
-    };
-
-    // Get PostGIS connector reference
-    let postgis_connector = vector_connector
-        .as_any()
-        .downcast_ref::<gridwalk_core::connector::postgis::PostgisConnector>()
-        .ok_or_else(|| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                axum::Json(json!({"error": "Vector connector is not a PostGIS connector"})),
-            )
-        })?;
-
-    // Get the tile data from PostGIS
-    let tile_data = postgis_connector
-        .get_tile(&layer_id, z, x, y)
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                axum::Json(json!({"error": format!("Failed to get tile: {}", e)})),
-            )
-        })?;
+    }
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to get tile: {}", e)})),
+        )
+    })?;
 
     // Check if tile is empty
     if tile_data.is_empty() {
@@ -69,3 +73,71 @@ pub async fn get_tile(
 
     Ok((StatusCode::OK, headers, tile_data))
 }
+
+/// Whether a layer groups the child layers of a multi-layer upload
+async fn has_children(
+    state: &AppState,
+    layer_id: Uuid,
+) -> Result<bool, (StatusCode, axum::Json<serde_json::Value>)> {
+    let children = Layer::children(layer_id, &*state.app_db)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to fetch child layers: {}", e)})),
+            )
+        })?;
+    Ok(!children.is_empty())
+}
+
+/// Whether a web mercator tile, with its buffer, overlaps a longitude and
+/// latitude bounding box.
+fn tile_intersects(bbox: &[f64], z: u32, x: u32, y: u32) -> bool {
+    let [min_lon, min_lat, max_lon, max_lat] = bbox else {
+        return true;
+    };
+
+    let tiles = 2f64.powi(z as i32);
+    let lon = |x: f64| x / tiles * 360.0 - 180.0;
+    let lat = |y: f64| (PI * (1.0 - 2.0 * y / tiles)).sinh().atan().to_degrees();
+
+    let (x, y) = (x as f64, y as f64);
+    let tile_west = lon(x - TILE_BUFFER);
+    let tile_east = lon(x + 1.0 + TILE_BUFFER);
+    let tile_north = lat(y - TILE_BUFFER);
+    let tile_south = lat(y + 1.0 + TILE_BUFFER);
+
+    tile_west <= *max_lon
+        && tile_east >= *min_lon
+        && tile_south <= *max_lat
+        && tile_north >= *min_lat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: [f64; 4] = [-0.5, 51.3, 0.3, 51.7];
+
+    #[test]
+    fn the_world_tile_intersects_everything() {
+        assert!(tile_intersects(&LONDON, 0, 0, 0));
+    }
+
+    #[test]
+    fn tiles_over_the_extent_intersect() {
+        assert!(tile_intersects(&LONDON, 10, 511, 340));
+        assert!(tile_intersects(&LONDON, 10, 512, 340));
+    }
+
+    #[test]
+    fn tiles_away_from_the_extent_do_not_intersect() {
+        assert!(!tile_intersects(&LONDON, 10, 0, 0));
+        assert!(!tile_intersects(&LONDON, 10, 520, 340));
+    }
+
+    #[test]
+    fn malformed_extents_never_skip_tiles() {
+        assert!(tile_intersects(&[0.0, 0.0, 1.0], 10, 0, 0));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::config::AppState;
+use crate::layer::ingest;
+use crate::layer::tus::parse_upload_metadata;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use futures::StreamExt;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::warn;
+use uuid::Uuid;
+
+/// POST endpoint checking a file, or the first part of one, before it is
+/// uploaded. The body is the raw file and the optional Upload-Metadata header
+/// carries the same ingest options as a TUS upload. Nothing is loaded: the
+/// response describes the layers found and any problems an ingest would hit.
+#[axum::debug_handler]
+pub async fn validate_upload(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, (StatusCode, axum::Json<serde_json::Value>)> {
+    // The layer name is ignored, the type tells GDAL how to read the file
+    let metadata = parse_upload_metadata(&headers)?.unwrap_or_default();
+    let upload_type = metadata.upload_type;
+    let ingest_options = metadata.ingest_options;
+
+    // Checked files are named apart from uploads and removed once inspected
+    let check_id = Uuid::new_v4();
+    let file_path = state.temp_data_path.join(format!("{}.preflight", check_id));
+    let work_dir = state
+        .temp_data_path
+        .join(format!("{}.preflight.extract", check_id));
+
+    let written = write_body(&state, &file_path, body).await;
+    let result = match written {
+        Ok(0) => Err((
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": "Request body is empty"})),
+        )),
+        Ok(_) => ingest::preflight_upload(
+            &state,
+            file_path.clone(),
+            upload_type,
+            work_dir,
+            ingest_options,
+        )
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to inspect file: {}", e)})),
+            )
+        }),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = fs::remove_file(&file_path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove preflight file {:?}: {}", file_path, e);
+    }
+
+    Ok(axum::Json(result?))
+}
+
+/// Stream the request body to a new file, up to the maximum upload size.
+/// Returns the number of bytes written.
+async fn write_body(
+    state: &AppState,
+    file_path: &std::path::Path,
+    body: Body,
+) -> Result<i64, (StatusCode, axum::Json<serde_json::Value>)> {
+    let mut file = fs::File::create(file_path).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to create file: {}", e)})),
+        )
+    })?;
+
+    let mut written: i64 = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(frame) = stream.next().await {
+        let data = frame.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                axum::Json(json!({"error": format!("Upload interrupted: {}", e)})),
+            )
+        })?;
+
+        written += data.len() as i64;
+        if written > state.max_upload_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                axum::Json(json!({
+                    "error": "File exceeds the maximum upload size",
+                    "max_size": state.max_upload_size
+                })),
+            ));
+        }
+
+        file.write_all(&data).await.map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to write file: {}", e)})),
+            )
+        })?;
+    }
+
+    file.flush().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to flush file: {}", e)})),
+        )
+    })?;
+    Ok(written)
+}
@@ -0,0 +1,231 @@
+use super::InvalidUpload;
+use anyhow::{Result, anyhow};
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Dataset file extensions looked for inside archives. Sidecar files such as
+/// .shx, .dbf, .prj and .cpg are picked up by GDAL next to their .shp.
+const DATASET_EXTENSIONS: &[&str] = &[
+    "shp", "gpkg", "geojson", "json", "fgb", "kml", "gml", "gpx", "tab", "mif", "csv", "xlsx",
+    "ods",
+];
+
+/// Archive formats accepted as uploads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+/// Detect an archive from the leading bytes of the uploaded file.
+pub fn detect_archive(path: &Path) -> Result<Option<ArchiveKind>> {
+    let mut magic = [0u8; 4];
+    let read = File::open(path)?.read(&mut magic)?;
+
+    Ok(match &magic[..read] {
+        [b'P', b'K', 0x03, 0x04] | [b'P', b'K', 0x05, 0x06]
+            if office_document_extension(path).is_none() =>
+        {
+            Some(ArchiveKind::Zip)
+        }
+        [0x1f, 0x8b, ..] => Some(ArchiveKind::TarGz),
+        _ => None,
+    })
+}
+
+/// XLSX and ODS spreadsheets are zip files too, but GDAL opens them directly.
+/// Returns the spreadsheet's extension, `None` for other files.
+fn office_document_extension(path: &Path) -> Option<&'static str> {
+    let archive = zip::ZipArchive::new(File::open(path).ok()?).ok()?;
+    archive.file_names().find_map(|name| match name {
+        "[Content_Types].xml" => Some("xlsx"),
+        "mimetype" => Some("ods"),
+        _ => None,
+    })
+}
+
+/// Extension GDAL needs to pick the driver for an upload that is not an
+/// archive. Uploads are stored without one, and formats such as CSV and
+/// XLSX are only recognised by it. Taken from the upload type, else from
+/// the content for spreadsheets. `None` when GDAL can go by the content.
+pub fn upload_extension(path: &Path, upload_type: Option<&str>) -> Option<String> {
+    let from_type = upload_type
+        .map(|upload_type| upload_type.to_lowercase())
+        .map(|upload_type| match upload_type.as_str() {
+            "geopackage" => "gpkg".to_string(),
+            "flatgeobuf" => "fgb".to_string(),
+            "excel" => "xlsx".to_string(),
+            _ => upload_type,
+        })
+        .filter(|extension| DATASET_EXTENSIONS.contains(&extension.as_str()));
+    from_type.or_else(|| office_document_extension(path).map(str::to_string))
+}
+
+/// Extract an archive into `dest`, refusing entries that escape it and
+/// stopping once more than `max_size` bytes have been written.
+pub fn extract_archive(path: &Path, kind: ArchiveKind, dest: &Path, max_size: u64) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    match kind {
+        ArchiveKind::Zip => extract_zip(path, dest, max_size),
+        ArchiveKind::TarGz => extract_tar_gz(path, dest, max_size),
+    }
+}
+
+fn extract_zip(path: &Path, dest: &Path, max_size: u64) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)
+        .map_err(|e| InvalidUpload(format!("Failed to read zip archive: {}", e)))?;
+
+    let mut extracted = 0u64;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .map_err(|e| InvalidUpload(format!("Failed to read zip archive: {}", e)))?;
+
+        // Skip links and entries with unsafe paths
+        let Some(relative_path) = entry.enclosed_name() else {
+            continue;
+        };
+        if entry.is_symlink() {
+            continue;
+        }
+
+        let out_path = dest.join(relative_path);
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path)?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Don't trust the declared size, count what is actually written
+        let remaining = max_size.saturating_sub(extracted);
+        let mut out_file = File::create(&out_path)?;
+        extracted += io::copy(&mut (&mut entry).take(remaining + 1), &mut out_file)?;
+        if extracted > max_size {
+            return Err(archive_too_large(max_size));
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_tar_gz(path: &Path, dest: &Path, max_size: u64) -> Result<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    let entries = archive
+        .entries()
+        .map_err(|e| InvalidUpload(format!("Failed to read tar.gz archive: {}", e)))?;
+
+    let mut extracted = 0u64;
+    for entry in entries {
+        let mut entry =
+            entry.map_err(|e| InvalidUpload(format!("Failed to read tar.gz archive: {}", e)))?;
+
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+
+        extracted += entry.size();
+        if extracted > max_size {
+            return Err(archive_too_large(max_size));
+        }
+
+        // `unpack_in` skips entries that would escape the destination
+        entry
+            .unpack_in(dest)
+            .map_err(|e| InvalidUpload(format!("Failed to extract tar.gz archive: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+fn archive_too_large(max_size: u64) -> anyhow::Error {
+    InvalidUpload(format!("Archive expands to more than {} bytes", max_size)).into()
+}
+
+/// Find the files in an extracted archive that GDAL can open as datasets,
+/// in a stable order.
+pub fn find_datasets(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut datasets = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| anyhow!("Invalid file name in archive: {:?}", path))?;
+
+            // Skip macOS resource forks and other hidden files
+            if file_name.starts_with('.') || file_name == "__MACOSX" {
+                continue;
+            }
+
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| extension.to_lowercase());
+
+            if path.is_dir() {
+                // File geodatabases are directories opened as a whole
+                if extension.as_deref() == Some("gdb") {
+                    datasets.push(path);
+                } else {
+                    pending.push(path);
+                }
+            } else if extension
+                .as_deref()
+                .is_some_and(|extension| DATASET_EXTENSIONS.contains(&extension))
+            {
+                datasets.push(path);
+            }
+        }
+    }
+
+    datasets.sort();
+    Ok(datasets)
+}
+
+/// Paths of the datasets to ingest for an uploaded file. Archives are
+/// extracted into `work_dir` first. Other files are linked into `work_dir`
+/// under the extension of their `upload_type` when GDAL needs one.
+pub fn prepare_sources(
+    upload_path: &Path,
+    upload_type: Option<&str>,
+    work_dir: &Path,
+    max_size: u64,
+) -> Result<Vec<PathBuf>> {
+    let kind = detect_archive(upload_path)?;
+
+    // Start from an empty directory when a previous attempt left files behind
+    if work_dir.exists() {
+        fs::remove_dir_all(work_dir)?;
+    }
+
+    let Some(kind) = kind else {
+        let Some(extension) = upload_extension(upload_path, upload_type) else {
+            return Ok(vec![upload_path.to_path_buf()]);
+        };
+        fs::create_dir_all(work_dir)?;
+        let source = work_dir.join(format!("upload.{}", extension));
+        fs::hard_link(upload_path, &source)?;
+        return Ok(vec![source]);
+    };
+
+    extract_archive(upload_path, kind, work_dir, max_size)?;
+
+    let datasets = find_datasets(work_dir)?;
+    if datasets.is_empty() {
+        return Err(InvalidUpload(
+            "Archive does not contain any supported dataset (shapefile, GeoPackage, GeoJSON, ...)"
+                .to_string(),
+        )
+        .into());
+    }
+
+    Ok(datasets)
+}
@@ -0,0 +1,257 @@
+use super::schema::{ColumnSchema, TableSchema};
+use super::tabular::{GeometrySource, parse_coordinate};
+use anyhow::Result;
+use gdal::spatial_ref::CoordTransform;
+use gdal::vector::{Feature, FieldValue, Geometry};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Result of encoding one feature
+pub enum Encoded {
+    /// The row was appended to the output. Values that could not be cast
+    /// to their mapped type were stored as the field's default, or NULL,
+    /// and are described in `cast_failures`.
+    Row { cast_failures: Vec<String> },
+    /// The feature was left out, with the reason
+    Skipped(String),
+}
+
+/// Encodes GDAL features as rows of a text format `COPY ... FROM STDIN`.
+pub struct RowEncoder {
+    storage_srid: i32,
+    transform: Option<CoordTransform>,
+    geometry_source: GeometrySource,
+    /// Source field name to position in the column list
+    positions: HashMap<String, usize>,
+    columns: Vec<ColumnSchema>,
+}
+
+impl RowEncoder {
+    pub fn new(schema: &TableSchema, storage_srid: i32, transform: Option<CoordTransform>) -> Self {
+        let positions = schema
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(position, column)| (column.source_name.clone(), position))
+            .collect();
+
+        RowEncoder {
+            storage_srid,
+            transform,
+            geometry_source: schema.geometry_source.clone(),
+            positions,
+            columns: schema.columns.clone(),
+        }
+    }
+
+    /// Append one feature as a COPY row (geometry first, then `feature_id`
+    /// and the fields). Features whose geometry cannot be built are skipped,
+    /// not written.
+    pub fn encode(&self, feature: &Feature, feature_id: u64, out: &mut String) -> Result<Encoded> {
+        let mut values: Vec<Option<String>> = vec![None; self.columns.len()];
+        let mut geometry_values: HashMap<String, String> = HashMap::new();
+        for (name, value) in feature.fields() {
+            let Some(text) = value.and_then(field_value_to_text) else {
+                continue;
+            };
+            if self.is_geometry_field(&name) {
+                geometry_values.insert(name.clone(), text.clone());
+            }
+            if let Some(position) = self.positions.get(&name) {
+                values[*position] = Some(text);
+            }
+        }
+
+        // Apply the field mapping's casts and defaults, a value that cannot
+        // be cast is stored as if it was missing
+        let mut cast_failures = Vec::new();
+        for (value, column) in values.iter_mut().zip(&self.columns) {
+            *value = match (value.take(), column.cast) {
+                (None, _) => column.default.clone(),
+                (Some(text), None) => Some(text),
+                (Some(text), Some(cast)) => match cast.convert(&text) {
+                    Some(converted) => Some(converted),
+                    None => {
+                        cast_failures.push(format!(
+                            "Value '{}' of {} is not a valid {}, stored as {}",
+                            text,
+                            column.source_name,
+                            cast,
+                            column.default.as_deref().unwrap_or("NULL")
+                        ));
+                        column.default.clone()
+                    }
+                },
+            };
+        }
+
+        let geometry = match self.build_geometry(feature, &geometry_values) {
+            Ok(geometry) => geometry,
+            Err(reason) => return Ok(Encoded::Skipped(reason)),
+        };
+
+        match geometry {
+            Some(geometry) if !geometry.is_empty() => {
+                let geometry = match storage_geometry(geometry, self.transform.as_ref()) {
+                    Ok(geometry) => geometry,
+                    Err(reason) => return Ok(Encoded::Skipped(reason)),
+                };
+                // PostGIS reads `SRID=n;<hex wkb>` as EWKB
+                write!(
+                    out,
+                    "SRID={};{}",
+                    self.storage_srid,
+                    hex::encode(geometry.wkb()?)
+                )?;
+            }
+            _ => out.push_str("\\N"),
+        }
+        write!(out, "\t{}", feature_id)?;
+
+        for value in values {
+            out.push('\t');
+            match value {
+                Some(value) => escape_copy_text(&value, out),
+                None => out.push_str("\\N"),
+            }
+        }
+        out.push('\n');
+        Ok(Encoded::Row { cast_failures })
+    }
+
+    /// Geometry of a feature as read from the source, before reprojection,
+    /// or the reason it cannot be built.
+    pub fn source_geometry<'a>(
+        &self,
+        feature: &'a Feature,
+    ) -> Result<Option<Cow<'a, Geometry>>, String> {
+        let geometry_values: HashMap<String, String> = feature
+            .fields()
+            .filter(|(name, _)| self.is_geometry_field(name))
+            .filter_map(|(name, value)| Some((name, value.and_then(field_value_to_text)?)))
+            .collect();
+        self.build_geometry(feature, &geometry_values)
+    }
+
+    fn is_geometry_field(&self, name: &str) -> bool {
+        match &self.geometry_source {
+            GeometrySource::Layer => false,
+            GeometrySource::Point { x_field, y_field } => name == x_field || name == y_field,
+            GeometrySource::Wkt { field } => name == field,
+        }
+    }
+
+    /// Geometry of a feature, or the reason it cannot be built.
+    fn build_geometry<'a>(
+        &self,
+        feature: &'a Feature,
+        geometry_values: &HashMap<String, String>,
+    ) -> Result<Option<Cow<'a, Geometry>>, String> {
+        let geometry = match &self.geometry_source {
+            GeometrySource::Layer => feature.geometry().map(Cow::Borrowed),
+            GeometrySource::Point { x_field, y_field } => {
+                let x = geometry_values.get(x_field);
+                let y = geometry_values.get(y_field);
+                let coordinates = x
+                    .and_then(|x| parse_coordinate(x))
+                    .zip(y.and_then(|y| parse_coordinate(y)));
+                let Some((x, y)) = coordinates else {
+                    return Err(format!(
+                        "Invalid coordinates: {}='{}', {}='{}'",
+                        x_field,
+                        x.map_or("", |x| x.as_str()),
+                        y_field,
+                        y.map_or("", |y| y.as_str())
+                    ));
+                };
+                let point = Geometry::from_wkt(&format!("POINT ({} {})", x, y))
+                    .map_err(|e| format!("Failed to build point: {}", e))?;
+                Some(Cow::Owned(point))
+            }
+            GeometrySource::Wkt { field } => {
+                let Some(wkt) = geometry_values
+                    .get(field)
+                    .filter(|wkt| !wkt.trim().is_empty())
+                else {
+                    return Err(format!("Missing WKT in {}", field));
+                };
+                let geometry = Geometry::from_wkt(wkt)
+                    .map_err(|e| format!("Invalid WKT in {}: {}", field, e))?;
+                Some(Cow::Owned(geometry))
+            }
+        };
+        Ok(geometry)
+    }
+}
+
+/// Geometry as it is stored: reprojected into the storage SRID and flattened
+/// to 2D, as layer tables have a 2D `geometry` column that rejects Z and M
+/// coordinates. Returns the reason when it cannot be reprojected.
+fn storage_geometry(
+    geometry: Cow<Geometry>,
+    transform: Option<&CoordTransform>,
+) -> Result<Geometry, String> {
+    let mut geometry = geometry.into_owned();
+    if let Some(transform) = transform {
+        geometry
+            .transform_inplace(transform)
+            .map_err(|e| format!("Failed to reproject: {}", e))?;
+    }
+    geometry.flatten_to_2d();
+    Ok(geometry)
+}
+
+/// Render a field value the way Postgres parses it for the mapped column type.
+fn field_value_to_text(value: FieldValue) -> Option<String> {
+    let text = match value {
+        FieldValue::IntegerValue(v) => v.to_string(),
+        FieldValue::Integer64Value(v) => v.to_string(),
+        FieldValue::RealValue(v) => v.to_string(),
+        FieldValue::StringValue(v) => v,
+        FieldValue::DateValue(v) => v.format("%Y-%m-%d").to_string(),
+        FieldValue::DateTimeValue(v) => v.to_rfc3339(),
+        FieldValue::IntegerListValue(v) => serde_json::to_string(&v).ok()?,
+        FieldValue::Integer64ListValue(v) => serde_json::to_string(&v).ok()?,
+        FieldValue::RealListValue(v) => serde_json::to_string(&v).ok()?,
+        FieldValue::StringListValue(v) => serde_json::to_string(&v).ok()?,
+    };
+    Some(text)
+}
+
+/// Escape a value for the COPY text format.
+fn escape_copy_text(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            // NUL bytes cannot be stored in Postgres text
+            '\0' => {}
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_geometries_are_flattened_to_2d() {
+        for (source, stored) in [
+            ("POINT Z (1 2 3)", "POINT (1 2)"),
+            ("LINESTRING ZM (0 0 1 2,1 1 3 4)", "LINESTRING (0 0,1 1)"),
+            (
+                "POLYGON M ((0 0 1,1 0 1,1 1 1,0 0 1))",
+                "POLYGON ((0 0,1 0,1 1,0 0))",
+            ),
+            ("POINT (1 2)", "POINT (1 2)"),
+        ] {
+            let geometry = Geometry::from_wkt(source).unwrap();
+            let geometry = storage_geometry(Cow::Owned(geometry), None).unwrap();
+            assert_eq!(geometry.wkt().unwrap(), stored, "{}", source);
+        }
+    }
+}
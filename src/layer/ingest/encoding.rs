@@ -0,0 +1,191 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Bytes of DBF records read to guess the encoding of a shapefile
+const DETECTION_SAMPLE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Offset of the language driver id in a DBF header
+const DBF_LANGUAGE_DRIVER_OFFSET: usize = 29;
+
+/// Size of the fixed part of a DBF header
+const DBF_HEADER_SIZE: usize = 32;
+
+/// Where the encoding of a source came from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingOrigin {
+    /// The `encoding` option of the upload
+    Requested,
+    /// Declared by the file, in a .cpg file or the DBF header
+    Declared,
+    /// Guessed from the attribute bytes
+    Detected,
+}
+
+/// Character encoding the attribute text of a source is read with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceEncoding {
+    /// Encoding name as GDAL takes it, e.g. `CP1252`
+    pub encoding: String,
+    pub origin: EncodingOrigin,
+}
+
+impl SourceEncoding {
+    /// GDAL open option making the driver decode text from this encoding,
+    /// `None` when the driver already follows the file's declaration
+    pub fn open_option(&self) -> Option<String> {
+        (self.origin != EncodingOrigin::Declared).then(|| format!("ENCODING={}", self.encoding))
+    }
+}
+
+/// Encoding to open a source with. Only shapefiles, whose DBF text GDAL
+/// decodes, take an encoding: the requested one, else the one declared by
+/// a .cpg file or the DBF language driver id, else a guess from the bytes.
+/// `None` for other formats and for DBFs holding only ASCII text.
+pub fn resolve_encoding(source: &Path, requested: Option<&str>) -> Result<Option<SourceEncoding>> {
+    let Some(dbf_path) = dbf_path(source) else {
+        return Ok(None);
+    };
+    if let Some(encoding) = requested {
+        return Ok(Some(SourceEncoding {
+            encoding: encoding.to_string(),
+            origin: EncodingOrigin::Requested,
+        }));
+    }
+
+    if let Some(cpg_path) = sidecar_path(source, "cpg") {
+        let declared = std::fs::read_to_string(cpg_path)?;
+        let declared = declared.trim();
+        if !declared.is_empty() {
+            return Ok(Some(SourceEncoding {
+                encoding: declared.to_string(),
+                origin: EncodingOrigin::Declared,
+            }));
+        }
+    }
+
+    let mut header = [0u8; DBF_HEADER_SIZE];
+    let mut file = File::open(&dbf_path)?;
+    if file.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let language_driver = header[DBF_LANGUAGE_DRIVER_OFFSET];
+    if language_driver != 0 {
+        // GDAL maps the id to its code page itself
+        return Ok(Some(SourceEncoding {
+            encoding: format!("LDID/{}", language_driver),
+            origin: EncodingOrigin::Declared,
+        }));
+    }
+
+    // Records start after the field descriptors
+    let header_length = u16::from_le_bytes([header[8], header[9]]) as usize;
+    let mut records = Vec::new();
+    file.take(DETECTION_SAMPLE_SIZE + header_length as u64)
+        .read_to_end(&mut records)?;
+    let records = records
+        .get(header_length.saturating_sub(DBF_HEADER_SIZE)..)
+        .unwrap_or_default();
+
+    Ok(
+        detect_text_encoding(records).map(|encoding| SourceEncoding {
+            encoding: encoding.to_string(),
+            origin: EncodingOrigin::Detected,
+        }),
+    )
+}
+
+/// Guess the encoding of legacy attribute text. Valid UTF-8 is taken as
+/// such, anything else as Windows-1252 unless it uses the few bytes that
+/// code page leaves undefined, which only ISO-8859-1 can hold.
+fn detect_text_encoding(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.is_ascii() {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return Some("UTF-8"),
+        // A character cut off at the end of the sample
+        Err(e) if e.error_len().is_none() => return Some("UTF-8"),
+        Err(_) => {}
+    }
+    if bytes
+        .iter()
+        .any(|byte| matches!(byte, 0x81 | 0x8d | 0x8f | 0x90 | 0x9d))
+    {
+        Some("ISO-8859-1")
+    } else {
+        Some("CP1252")
+    }
+}
+
+/// The DBF of a shapefile source, `None` for other formats
+fn dbf_path(source: &Path) -> Option<PathBuf> {
+    let extension = source.extension()?.to_str()?;
+    if extension.eq_ignore_ascii_case("dbf") {
+        return Some(source.to_path_buf());
+    }
+    if !extension.eq_ignore_ascii_case("shp") {
+        return None;
+    }
+    sidecar_path(source, "dbf")
+}
+
+/// Existing file next to `source` with the same stem and another extension,
+/// in either case
+fn sidecar_path(source: &Path, extension: &str) -> Option<PathBuf> {
+    [extension.to_string(), extension.to_uppercase()]
+        .into_iter()
+        .map(|extension| source.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Check an `encoding` option and return its canonical form.
+pub fn normalize_encoding(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty()
+        || value.len() > 32
+        || !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(
+            "Encoding must be a character set name such as 'UTF-8', 'CP1252' or 'ISO-8859-1'"
+                .to_string(),
+        );
+    }
+    Ok(value.to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_needs_no_encoding() {
+        assert_eq!(detect_text_encoding(b"Main Street"), None);
+        assert_eq!(detect_text_encoding(b""), None);
+    }
+
+    #[test]
+    fn detects_utf8() {
+        assert_eq!(detect_text_encoding("Café".as_bytes()), Some("UTF-8"));
+    }
+
+    #[test]
+    fn detects_utf8_cut_off_at_the_end_of_the_sample() {
+        assert_eq!(detect_text_encoding(b"Caf\xc3"), Some("UTF-8"));
+    }
+
+    #[test]
+    fn detects_cp1252() {
+        assert_eq!(detect_text_encoding(b"Caf\xe9 \x80 5"), Some("CP1252"));
+    }
+
+    #[test]
+    fn bytes_undefined_in_cp1252_are_iso_8859_1() {
+        assert_eq!(detect_text_encoding(b"Caf\xe9 \x81"), Some("ISO-8859-1"));
+    }
+}
@@ -0,0 +1,36 @@
+use super::{FeatureError, IngestStage, InvalidUpload};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Why the last ingest of a layer failed, stored on the layer for clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IngestError {
+    /// Step the ingest had reached when it failed
+    pub stage: IngestStage,
+    pub message: String,
+    /// Source layer being read, when the failure is tied to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_layer: Option<String>,
+    /// Position of the failing feature in its source layer, counted from 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feature_index: Option<u64>,
+    /// Whether the file itself is at fault, so retrying it cannot succeed
+    #[serde(default)]
+    pub invalid_upload: bool,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl IngestError {
+    /// Describe a failed ingest that had reached `stage`.
+    pub fn new(stage: IngestStage, error: &anyhow::Error) -> Self {
+        let feature = error.downcast_ref::<FeatureError>();
+        IngestError {
+            stage,
+            message: error.to_string(),
+            source_layer: feature.map(|feature| feature.source_layer.clone()),
+            feature_index: feature.map(|feature| feature.feature_index),
+            invalid_upload: error.is::<InvalidUpload>(),
+            failed_at: Utc::now(),
+        }
+    }
+}
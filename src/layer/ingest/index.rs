@@ -0,0 +1,61 @@
+use super::{GEOMETRY_COLUMN, TableSchema, quote_ident};
+use anyhow::{Result, anyhow};
+use sqlx::PgConnection;
+use tracing::warn;
+
+/// Index a freshly loaded table: a GiST index on the geometry, a B-tree
+/// index on each requested attribute column, then fresh planner statistics.
+/// Attributes match a source field or column name; names the table does not
+/// have are skipped. Returns the indexed attribute columns.
+pub async fn create_indexes(
+    conn: &mut PgConnection,
+    schema: &TableSchema,
+    index_fields: &[String],
+) -> Result<Vec<String>> {
+    let table = schema.qualified_name();
+
+    // Index names are left to Postgres, which keeps them unique and short
+    sqlx::query(&format!(
+        "CREATE INDEX ON {} USING GIST ({})",
+        table,
+        quote_ident(GEOMETRY_COLUMN)
+    ))
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| anyhow!("Failed to create spatial index: {}", e))?;
+
+    let mut indexed = Vec::new();
+    for field in index_fields {
+        let Some(column) = schema
+            .columns
+            .iter()
+            .find(|column| &column.source_name == field || &column.name == field)
+        else {
+            warn!(
+                "Layer {} has no field '{}' to index",
+                schema.source_layer, field
+            );
+            continue;
+        };
+        if indexed.contains(&column.name) {
+            continue;
+        }
+
+        sqlx::query(&format!(
+            "CREATE INDEX ON {} ({})",
+            table,
+            quote_ident(&column.name)
+        ))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Failed to index field '{}': {}", field, e))?;
+        indexed.push(column.name.clone());
+    }
+
+    sqlx::query(&format!("ANALYZE {}", table))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Failed to analyze layer table: {}", e))?;
+
+    Ok(indexed)
+}
@@ -0,0 +1,257 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use strum_macros::Display;
+
+/// Column type a source field can be cast to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Integer,
+    Bigint,
+    Double,
+    Boolean,
+    Date,
+    Timestamp,
+    Jsonb,
+}
+
+impl FieldType {
+    /// Postgres column type
+    pub fn pg_type(self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Integer => "integer",
+            FieldType::Bigint => "bigint",
+            FieldType::Double => "double precision",
+            FieldType::Boolean => "boolean",
+            FieldType::Date => "date",
+            FieldType::Timestamp => "timestamptz",
+            FieldType::Jsonb => "jsonb",
+        }
+    }
+
+    /// Field type of a Postgres column type, `None` for types no field maps to
+    pub fn from_pg_type(pg_type: &str) -> Option<Self> {
+        [
+            FieldType::Text,
+            FieldType::Integer,
+            FieldType::Bigint,
+            FieldType::Double,
+            FieldType::Boolean,
+            FieldType::Date,
+            FieldType::Timestamp,
+            FieldType::Jsonb,
+        ]
+        .into_iter()
+        .find(|field_type| field_type.pg_type() == pg_type)
+    }
+
+    /// Render a source value the way Postgres parses it for this type, `None`
+    /// when the value cannot be converted.
+    pub fn convert(self, value: &str) -> Option<String> {
+        let trimmed = value.trim();
+        match self {
+            FieldType::Text => Some(value.to_string()),
+            FieldType::Integer => parse_integer(trimmed)
+                .filter(|v| i32::try_from(*v).is_ok())
+                .map(|v| v.to_string()),
+            FieldType::Bigint => parse_integer(trimmed).map(|v| v.to_string()),
+            FieldType::Double => trimmed
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .map(|v| v.to_string()),
+            FieldType::Boolean => match trimmed.to_lowercase().as_str() {
+                "t" | "true" | "y" | "yes" | "on" | "1" => Some("true".to_string()),
+                "f" | "false" | "n" | "no" | "off" | "0" => Some("false".to_string()),
+                _ => None,
+            },
+            FieldType::Date => NaiveDate::parse_from_str(trimmed, "%Y-%m-%d")
+                .ok()
+                .or_else(|| {
+                    DateTime::parse_from_rfc3339(trimmed)
+                        .ok()
+                        .map(|v| v.date_naive())
+                })
+                .or_else(|| parse_naive_datetime(trimmed).map(|v| v.date()))
+                .map(|v| v.format("%Y-%m-%d").to_string()),
+            FieldType::Timestamp => DateTime::parse_from_rfc3339(trimmed)
+                .ok()
+                .map(|v| v.to_rfc3339())
+                .or_else(|| {
+                    // Times without an offset are taken as UTC
+                    parse_naive_datetime(trimmed)
+                        .or_else(|| {
+                            NaiveDate::parse_from_str(trimmed, "%Y-%m-%d")
+                                .ok()
+                                .and_then(|v| v.and_hms_opt(0, 0, 0))
+                        })
+                        .map(|v| v.and_utc().to_rfc3339())
+                }),
+            // Text that is not JSON is stored as a JSON string
+            FieldType::Jsonb => match serde_json::from_str::<serde_json::Value>(trimmed) {
+                Ok(_) => Some(trimmed.to_string()),
+                Err(_) => serde_json::to_string(value).ok(),
+            },
+        }
+    }
+}
+
+/// Whole number, also written with a zero fraction as some formats store
+/// integers as reals.
+fn parse_integer(text: &str) -> Option<i64> {
+    text.parse::<i64>().ok().or_else(|| {
+        text.parse::<f64>()
+            .ok()
+            .filter(|v| v.fract() == 0.0 && v.abs() < i64::MAX as f64)
+            .map(|v| v as i64)
+    })
+}
+
+fn parse_naive_datetime(text: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+/// How one source field is loaded
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldRule {
+    /// Column name to load the field into instead of one derived from the
+    /// source name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+    /// Column type overriding the one guessed from the source
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub cast: Option<FieldType>,
+    /// Leave the field out of the table
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exclude: bool,
+    /// Value stored when the source value is null
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<serde_json::Value>,
+}
+
+impl FieldRule {
+    /// The default as text, JSON arrays and objects as their JSON text
+    pub fn default_text(&self) -> Option<String> {
+        match self.default.as_ref()? {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(text) => Some(text.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+/// Client supplied rules for the fields of an upload, keyed by source field
+/// name. Fields without a rule are loaded as detected.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FieldMapping(pub BTreeMap<String, FieldRule>);
+
+impl FieldMapping {
+    /// Parse a mapping document such as
+    /// `{"ZIP": {"rename": "zip_code", "type": "text"}, "tmp": {"exclude": true}}`.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let mapping: FieldMapping =
+            serde_json::from_str(json).map_err(|e| format!("Invalid field_mapping: {}", e))?;
+        mapping.validate()?;
+        Ok(mapping)
+    }
+
+    /// Check rules that contradict each other.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut renamed = HashSet::new();
+        for (field, rule) in &self.0 {
+            if rule.exclude
+                && (rule.rename.is_some() || rule.cast.is_some() || rule.default.is_some())
+            {
+                return Err(format!(
+                    "Field '{}' is excluded and cannot also be renamed, cast or given a default",
+                    field
+                ));
+            }
+            if let Some(rename) = &rule.rename {
+                if rename.trim().is_empty() {
+                    return Err(format!(
+                        "Field '{}' cannot be renamed to an empty name",
+                        field
+                    ));
+                }
+                if !renamed.insert(rename.trim().to_lowercase()) {
+                    return Err(format!("More than one field is renamed to '{}'", rename));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rule for a source field, matching its name exactly or else ignoring case.
+    pub fn rule(&self, source_name: &str) -> Option<&FieldRule> {
+        self.0.get(source_name).or_else(|| {
+            self.0
+                .iter()
+                .find(|(field, _)| field.eq_ignore_ascii_case(source_name))
+                .map(|(_, rule)| rule)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules() {
+        let mapping = FieldMapping::parse(
+            r#"{"ZIP": {"rename": "zip_code", "type": "text"}, "tmp": {"exclude": true}}"#,
+        )
+        .unwrap();
+        let zip = mapping.rule("ZIP").unwrap();
+        assert_eq!(zip.rename.as_deref(), Some("zip_code"));
+        assert_eq!(zip.cast, Some(FieldType::Text));
+        assert!(mapping.rule("tmp").unwrap().exclude);
+    }
+
+    #[test]
+    fn rule_lookup_falls_back_to_ignoring_case() {
+        let mapping = FieldMapping::parse(r#"{"Zip": {"type": "text"}}"#).unwrap();
+        assert!(mapping.rule("ZIP").is_some());
+        assert!(mapping.rule("postcode").is_none());
+    }
+
+    #[test]
+    fn rejects_excluded_fields_with_other_rules() {
+        for rule in [
+            r#"{"exclude": true, "rename": "a"}"#,
+            r#"{"exclude": true, "type": "text"}"#,
+            r#"{"exclude": true, "default": 0}"#,
+        ] {
+            let json = format!(r#"{{"field": {}}}"#, rule);
+            assert!(FieldMapping::parse(&json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn rejects_conflicting_renames() {
+        let error = FieldMapping::parse(r#"{"a": {"rename": "name"}, "b": {"rename": " NAME "}}"#)
+            .unwrap_err();
+        assert!(error.contains("More than one field"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_empty_renames() {
+        assert!(FieldMapping::parse(r#"{"a": {"rename": "  "}}"#).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_rule_keys_and_types() {
+        assert!(FieldMapping::parse(r#"{"a": {"cast": "text"}}"#).is_err());
+        assert!(FieldMapping::parse(r#"{"a": {"type": "varchar"}}"#).is_err());
+        assert!(FieldMapping::parse("[]").is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use super::{
+    ColumnSchema, GEOMETRY_COLUMN, ID_COLUMN, IngestOptions, InvalidUpload, LoadedLayer,
+    TableSchema, point_view_at, quote_ident, version_table_name,
+};
+use crate::config::AppState;
+use crate::layer::{Layer, LayerStatus, LayerVersion};
+use anyhow::{Result, anyhow};
+use gridwalk_core::LayerCore;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, Postgres, Transaction};
+use strum_macros::{Display, EnumString};
+use uuid::Uuid;
+
+/// How an upload is loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Display, EnumString, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum IngestMode {
+    /// Load into a new layer
+    #[default]
+    Create,
+    /// Add the features to an existing layer
+    Append,
+    /// Swap an existing layer's data for the upload
+    Replace,
+    /// Replace the features of an existing layer that share a key, add the rest
+    Upsert,
+}
+
+/// Existing layer an upload is loaded into. The upload is first copied into
+/// a staging table of its own, then combined with the target's active data
+/// into a new version table. The layer's table name is a view over the
+/// active version, switched on the loading transaction, so readers only
+/// ever see the old or the new data. The target's row is locked on the app
+/// database for the whole ingest, until the new version is recorded, so
+/// merges and rollbacks of the same layer never work from the same version.
+#[derive(Debug, Clone)]
+pub struct MergeTarget {
+    pub layer_id: Uuid,
+    /// Database schema holding the layer's tables
+    pub data_schema: String,
+    /// Name the layer's data is served under
+    pub table_name: String,
+    /// EPSG code the target stores its geometries in
+    pub srid: i32,
+    pub columns: Vec<ColumnSchema>,
+    /// Version the target serves, `None` while its data is a plain table
+    pub current_version: Option<i32>,
+    /// Version the merge creates
+    pub version: i32,
+    pub mode: IngestMode,
+    /// Field matching features for upserts
+    pub key_field: Option<String>,
+}
+
+/// The new version table once the upload has been merged into it
+#[derive(Debug, Clone)]
+pub struct MergedTable {
+    pub schema: TableSchema,
+    pub feature_count: u64,
+    pub version: i32,
+}
+
+impl MergeTarget {
+    /// Look up the layer an upload targets, `None` for uploads creating a new
+    /// layer. The target must be ready and have a table of its own.
+    pub async fn load(state: &AppState, options: &IngestOptions) -> Result<Option<Self>> {
+        let Some(layer_id) = options.target_layer_id else {
+            return Ok(None);
+        };
+        let target = Layer::get(layer_id, &*state.app_db)
+            .await
+            .map_err(|e| target_lookup_error(layer_id, e))?;
+        let latest_version = LayerVersion::latest_number(layer_id, &*state.app_db).await?;
+        Self::from_layer(state, options, target, latest_version).map(Some)
+    }
+
+    /// Like `load`, with the target read under a lock on its row. The lock
+    /// is held by the returned app database transaction, which the ingest
+    /// keeps open until the data side has committed, and which records the
+    /// new version.
+    pub async fn lock(
+        state: &AppState,
+        options: &IngestOptions,
+    ) -> Result<Option<(Self, Transaction<'static, Postgres>)>> {
+        let Some(layer_id) = options.target_layer_id else {
+            return Ok(None);
+        };
+        let mut tx = state.app_db.begin().await?;
+        let target = Layer::get_for_update(layer_id, &mut *tx)
+            .await
+            .map_err(|e| target_lookup_error(layer_id, e))?;
+        let latest_version = LayerVersion::latest_number(layer_id, &mut *tx).await?;
+        let target = Self::from_layer(state, options, target, latest_version)?;
+        Ok(Some((target, tx)))
+    }
+
+    fn from_layer(
+        state: &AppState,
+        options: &IngestOptions,
+        target: Layer,
+        latest_version: Option<i32>,
+    ) -> Result<Self> {
+        if target.status != LayerStatus::Ready {
+            return Err(InvalidUpload(format!("Target layer {} is not ready", target.id)).into());
+        }
+        let (Some(table_name), Some(srid)) = (target.table_name, target.srid) else {
+            return Err(InvalidUpload(format!(
+                "Target layer {} has no table of its own",
+                target.id
+            ))
+            .into());
+        };
+
+        Ok(MergeTarget {
+            layer_id: target.id,
+            data_schema: state.layer_schema.clone(),
+            table_name,
+            srid,
+            columns: target.fields.unwrap_or_default(),
+            current_version: target.current_version,
+            // The original data becomes version 1 on the first merge
+            version: latest_version.unwrap_or(1) + 1,
+            mode: options.mode,
+            key_field: options.key_field.clone(),
+        })
+    }
+
+    /// Check that an uploaded layer fits the target. Appended and upserted
+    /// fields must exist in the target with the same type, a replacement
+    /// must keep every field of the target.
+    pub fn check_compatible(&self, schema: &TableSchema) -> Result<()> {
+        let replace = self.mode == IngestMode::Replace;
+        let (required, available) = if replace {
+            (&self.columns, &schema.columns)
+        } else {
+            (&schema.columns, &self.columns)
+        };
+
+        for column in required {
+            let Some(other) = available.iter().find(|other| other.name == column.name) else {
+                return Err(InvalidUpload(format!(
+                    "Field '{}' is missing from the {}",
+                    column.source_name,
+                    if replace { "upload" } else { "target layer" }
+                ))
+                .into());
+            };
+            if other.pg_type != column.pg_type {
+                let (upload_type, target_type) = if replace {
+                    (&other.pg_type, &column.pg_type)
+                } else {
+                    (&column.pg_type, &other.pg_type)
+                };
+                return Err(InvalidUpload(format!(
+                    "Field '{}' is {} in the upload but {} in the target layer",
+                    column.source_name, upload_type, target_type
+                ))
+                .into());
+            }
+        }
+
+        if self.mode == IngestMode::Upsert {
+            let key = self.key_column(schema)?;
+            if !self.columns.iter().any(|column| column.name == key.name) {
+                return Err(InvalidUpload(format!(
+                    "Key field '{}' is missing from the target layer",
+                    key.source_name
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the new version from the active data and a loaded staging
+    /// table, drop the staging table and serve the new version.
+    pub async fn merge(
+        &self,
+        conn: &mut PgConnection,
+        staging: &TableSchema,
+    ) -> Result<MergedTable> {
+        let qualified =
+            |table: &str| format!("{}.{}", quote_ident(&self.data_schema), quote_ident(table));
+        let staging_table = staging.qualified_name();
+
+        // A layer that was never merged into keeps its data in a plain table,
+        // which is kept as version 1
+        let current_version = match self.current_version {
+            Some(current_version) => current_version,
+            None => {
+                sqlx::query(&format!(
+                    "ALTER TABLE {} RENAME TO {}",
+                    qualified(&self.table_name),
+                    quote_ident(&version_table_name(&self.table_name, 1))
+                ))
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Failed to keep the original data as version 1: {}", e))?;
+                1
+            }
+        };
+        let current_table = qualified(&version_table_name(&self.table_name, current_version));
+        let version_name = version_table_name(&self.table_name, self.version);
+        let version_table = qualified(&version_name);
+
+        let columns = match self.mode {
+            IngestMode::Replace => {
+                sqlx::query(&format!(
+                    "ALTER TABLE {} RENAME TO {}",
+                    staging_table,
+                    quote_ident(&version_name)
+                ))
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Failed to store the new version: {}", e))?;
+                staging.columns.clone()
+            }
+            IngestMode::Append | IngestMode::Upsert => {
+                sqlx::query(&format!(
+                    "CREATE TABLE {} (LIKE {} INCLUDING CONSTRAINTS INCLUDING INDEXES)",
+                    version_table, current_table
+                ))
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Failed to create the new version: {}", e))?;
+
+                // Upserted features replace the current ones sharing their key
+                let replaced_filter = match self.mode {
+                    IngestMode::Upsert => {
+                        let key = quote_ident(&self.key_column(staging)?.name);
+                        format!(
+                            " WHERE NOT EXISTS (SELECT 1 FROM {} AS staging WHERE staging.{} = active.{})",
+                            staging_table, key, key
+                        )
+                    }
+                    _ => String::new(),
+                };
+                sqlx::query(&format!(
+                    "INSERT INTO {} SELECT * FROM {} AS active{}",
+                    version_table, current_table, replaced_filter
+                ))
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Failed to copy the current features: {}", e))?;
+
+                // New features are numbered after the kept ones
+                let id = quote_ident(ID_COLUMN);
+                let mut column_names = vec![quote_ident(GEOMETRY_COLUMN)];
+                column_names.extend(
+                    staging
+                        .columns
+                        .iter()
+                        .map(|column| quote_ident(&column.name)),
+                );
+                let column_names = column_names.join(", ");
+                sqlx::query(&format!(
+                    "INSERT INTO {} ({}, {}) \
+                     SELECT last.id + staging.{}, {} \
+                     FROM {} AS staging, (SELECT COALESCE(MAX({}), 0) AS id FROM {}) AS last \
+                     ORDER BY staging.{}",
+                    version_table,
+                    id,
+                    column_names,
+                    id,
+                    column_names,
+                    staging_table,
+                    id,
+                    version_table,
+                    id
+                ))
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Failed to add features to the target layer: {}", e))?;
+
+                sqlx::query(&format!("DROP TABLE {}", staging_table))
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(|e| anyhow!("Failed to drop the staging table: {}", e))?;
+                sqlx::query(&format!("ANALYZE {}", version_table))
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(|e| anyhow!("Failed to analyze layer table: {}", e))?;
+                self.columns.clone()
+            }
+            IngestMode::Create => unreachable!("new layers are not merged"),
+        };
+
+        point_view_at(conn, &self.data_schema, &self.table_name, &version_name).await?;
+
+        let feature_count: i64 =
+            sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", version_table))
+                .fetch_one(&mut *conn)
+                .await?;
+
+        Ok(MergedTable {
+            schema: TableSchema {
+                table_name: version_name,
+                columns,
+                ..staging.clone()
+            },
+            feature_count: feature_count as u64,
+            version: self.version,
+        })
+    }
+
+    /// Record the version a merge created on the target layer and make it
+    /// the layer's active data. Runs on the app database transaction
+    /// returned by `lock`. `upload_id` is the upload the version was loaded
+    /// from.
+    pub async fn record_version(
+        &self,
+        conn: &mut PgConnection,
+        upload_id: Uuid,
+        loaded: &LoadedLayer,
+    ) -> Result<()> {
+        let target = Layer::get(self.layer_id, &mut *conn).await?;
+        let version = loaded
+            .version
+            .ok_or_else(|| anyhow!("Merged layer {} has no version", self.layer_id))?;
+
+        // The first merge keeps the original data as version 1
+        if target.current_version.is_none() {
+            let original = LayerVersion {
+                layer_id: target.id,
+                version: 1,
+                table_name: version_table_name(&self.table_name, 1),
+                upload_id: None,
+                mode: IngestMode::Create,
+                srid: target.srid,
+                bbox: target.bbox.clone(),
+                geometry_type: target.geometry_type.clone(),
+                feature_count: target.feature_count.unwrap_or_default(),
+                fields: target.fields.clone(),
+                created_at: target.created_at,
+            };
+            original.save(&mut *conn).await?;
+        }
+
+        let created = LayerVersion {
+            layer_id: target.id,
+            version,
+            table_name: version_table_name(&self.table_name, version),
+            upload_id: Some(upload_id),
+            mode: self.mode,
+            srid: Some(loaded.srid),
+            bbox: loaded.bbox.clone(),
+            geometry_type: loaded.geometry_type.clone(),
+            feature_count: loaded.feature_count as i64,
+            fields: Some(loaded.fields.clone()),
+            created_at: chrono::Utc::now(),
+        };
+        created.save(&mut *conn).await?;
+
+        Layer::mark_loaded(target.id, loaded, &mut *conn).await?;
+        Ok(())
+    }
+
+    fn key_column<'a>(&self, schema: &'a TableSchema) -> Result<&'a ColumnSchema> {
+        let key_field = self
+            .key_field
+            .as_deref()
+            .ok_or_else(|| InvalidUpload("Upserts need a 'key_field'".to_string()))?;
+        schema
+            .columns
+            .iter()
+            .find(|column| column.source_name == key_field || column.name == key_field)
+            .ok_or_else(|| {
+                InvalidUpload(format!(
+                    "Key field '{}' is missing from the upload",
+                    key_field
+                ))
+                .into()
+            })
+    }
+}
+
+/// Uploads into a layer that does not exist cannot be retried
+fn target_lookup_error(layer_id: Uuid, e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::RowNotFound) => {
+            InvalidUpload(format!("Target layer {} not found", layer_id)).into()
+        }
+        _ => e,
+    }
+}
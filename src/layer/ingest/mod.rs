@@ -0,0 +1,739 @@
+use crate::config::AppState;
+use crate::layer::Layer;
+use anyhow::{Result, anyhow, bail};
+use gdal::vector::LayerAccess;
+use gdal::{Dataset, DatasetOptions, GdalOpenFlags};
+use gridwalk_core::connector::postgis::PostgisConnector;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+mod archive;
+mod copy;
+mod encoding;
+mod failure;
+mod index;
+mod mapping;
+mod merge;
+mod options;
+mod preflight;
+mod progress;
+mod report;
+mod reproject;
+mod schema;
+mod stats;
+mod tabular;
+mod tile;
+mod validate;
+mod version;
+
+use copy::{Encoded, RowEncoder};
+pub use encoding::*;
+pub use failure::*;
+use index::create_indexes;
+pub use mapping::*;
+pub use merge::*;
+pub use options::*;
+pub use preflight::*;
+pub use progress::*;
+pub use report::*;
+use reproject::storage_transform;
+pub use schema::*;
+use stats::{TableStats, table_stats};
+pub use tabular::*;
+pub use tile::*;
+pub use validate::*;
+pub use version::*;
+
+/// Number of features encoded into one COPY message
+const COPY_BATCH_SIZE: usize = 1000;
+
+/// Extracted archives may be at most this many times the maximum upload size
+const MAX_ARCHIVE_EXPANSION: u64 = 20;
+
+/// Ingest failure caused by the uploaded file itself. Retrying cannot fix
+/// it, so the job fails without further attempts.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct InvalidUpload(pub String);
+
+/// Ingest failure on a single feature of a source layer
+#[derive(Debug, Error)]
+#[error("Failed to encode feature {feature_index} of layer '{source_layer}': {message}")]
+pub struct FeatureError {
+    pub source_layer: String,
+    /// Position of the feature in its source layer, counted from 0
+    pub feature_index: u64,
+    pub message: String,
+}
+
+/// A source layer loaded into its own table
+#[derive(Debug, Clone)]
+pub struct LoadedLayer {
+    /// Gridwalk layer the table belongs to, the upload itself for
+    /// single-layer files or a new child layer otherwise
+    pub layer_id: Uuid,
+    pub source_layer: String,
+    pub table_name: String,
+    /// EPSG code of the source data, before reprojection
+    pub source_srid: Option<i32>,
+    /// EPSG code the geometries are stored in
+    pub srid: i32,
+    /// Extent in EPSG:4326 as [min x, min y, max x, max y]
+    pub bbox: Option<Vec<f64>>,
+    pub geometry_type: Option<String>,
+    pub fields: Vec<ColumnSchema>,
+    pub feature_count: u64,
+    pub report: IngestReport,
+    /// Version created in an existing layer, `None` for new layers
+    pub version: Option<i32>,
+}
+
+/// What the database task learned about one loaded table
+struct LoadedTable {
+    inserted: u64,
+    validation: GeometryValidation,
+    stats: TableStats,
+    indexed_fields: Vec<String>,
+    /// The target table, for uploads into an existing layer
+    merged: Option<MergedTable>,
+}
+
+/// Result of a successful ingest
+#[derive(Debug, Clone)]
+pub struct IngestOutcome {
+    pub layers: Vec<LoadedLayer>,
+}
+
+impl IngestOutcome {
+    pub fn feature_count(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.feature_count).sum()
+    }
+}
+
+/// An opened source dataset and the encoding its attribute text is read with
+struct SourceDataset {
+    dataset: Dataset,
+    encoding: Option<SourceEncoding>,
+}
+
+/// Messages streamed from the GDAL task to the database task
+enum IngestMessage {
+    /// Start of a new source layer and the table it is loaded into
+    Table(TableSchema),
+    /// A batch of COPY rows for the current table and how many it holds
+    Rows(String, u64),
+}
+
+/// Load a fully uploaded layer file into the connection database.
+/// Every selected source layer gets its own table. Features are streamed with
+/// `COPY ... FROM STDIN` in batches, and table creation and loading run inside
+/// a single transaction, so a failed ingest leaves no partial data behind.
+/// Uploads into an existing layer are loaded into a staging table, then
+/// merged into the target table on the same transaction. The target's row
+/// stays locked on the app database until its new version is recorded.
+/// Progress is reported to `progress`, which is marked complete on success.
+pub async fn ingest_layer(
+    state: &AppState,
+    layer: &Layer,
+    progress: &Arc<ProgressTracker>,
+) -> Result<IngestOutcome> {
+    let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+    let postgis_connector = postgis_connector(state)?;
+
+    // Archives are extracted next to the upload while they are read
+    let work_dir = state.temp_data_path.join(format!("{}.extract", layer.id));
+    let max_extracted_size = (state.max_upload_size as u64).saturating_mul(MAX_ARCHIVE_EXPANSION);
+
+    // New rows must match the geometries already stored in a target layer
+    let (merge_target, mut target_lock) =
+        match MergeTarget::lock(state, &layer.ingest_options).await? {
+            Some((merge_target, target_lock)) => (Some(merge_target), Some(target_lock)),
+            None => (None, None),
+        };
+    let storage_srid = merge_target
+        .as_ref()
+        .map_or(state.ingest_config.storage_srid, |target| target.srid);
+
+    let (message_sender, mut message_receiver) = mpsc::channel::<IngestMessage>(16);
+
+    // Spawn blocking task for GDAL processing to avoid Send issues
+    let layer_id = layer.id;
+    let options = layer.ingest_options.clone();
+    let geometry_policy = layer.ingest_options.geometry_policy;
+    let index_fields = layer
+        .ingest_options
+        .index_fields
+        .clone()
+        .unwrap_or_default();
+    let gdal_work_dir = work_dir.clone();
+    let gdal_progress = progress.clone();
+    let gdal_merge_target = merge_target.clone();
+    let layer_schema = state.layer_schema.clone();
+    let upload_type = layer.upload_type.clone();
+    let gdal_handle = tokio::task::spawn_blocking(move || -> Result<Vec<LoadedLayer>> {
+        let from_archive = archive::detect_archive(&upload_file_path)?.is_some();
+        if from_archive {
+            gdal_progress.set_stage(IngestStage::Extracting);
+        }
+        let sources = archive::prepare_sources(
+            &upload_file_path,
+            upload_type.as_deref(),
+            &gdal_work_dir,
+            max_extracted_size,
+        )?;
+        gdal_progress.set_stage(IngestStage::Loading);
+        let datasets = open_sources(&sources, from_archive, options.encoding.as_deref())?;
+
+        // Every layer of every dataset, as (dataset index, layer index)
+        let mut source_indexes = Vec::new();
+        let mut source_names = Vec::new();
+        for (dataset_index, source) in datasets.iter().enumerate() {
+            for (layer_index, source_layer) in source.dataset.layers().enumerate() {
+                source_indexes.push((dataset_index, layer_index));
+                source_names.push(source_layer.name());
+            }
+        }
+        let selected = select_source_layers(&source_names, options.layers.as_deref())?;
+        if gdal_merge_target.is_some() && selected.len() != 1 {
+            return Err(InvalidUpload(format!(
+                "Uploads into an existing layer must have a single source layer, \
+                 use 'layers' to pick one of: {}",
+                source_names.join(", ")
+            ))
+            .into());
+        }
+
+        // Only known when every selected layer can count its features cheaply
+        let features_total = selected
+            .iter()
+            .map(|index| {
+                let (dataset_index, layer_index) = source_indexes[*index];
+                datasets[dataset_index]
+                    .dataset
+                    .layer(layer_index)
+                    .ok()
+                    .and_then(|source_layer| source_layer.try_feature_count())
+            })
+            .sum::<Option<u64>>();
+        gdal_progress.set_total(features_total);
+
+        // A single source layer is loaded into the upload itself, several
+        // become child layers grouped under it
+        let single = selected.len() == 1;
+        let mut loaded = Vec::with_capacity(selected.len());
+        for index in selected {
+            let target_id = if single { layer_id } else { Uuid::new_v4() };
+            let table_name = target_id.to_string();
+
+            let (dataset_index, layer_index) = source_indexes[index];
+            let mut source_layer = datasets[dataset_index]
+                .dataset
+                .layer(layer_index)
+                .map_err(|e| anyhow!("Failed to read layer {}: {}", source_names[index], e))?;
+
+            let schema = extract_layer_schema(&source_layer, &layer_schema, &table_name, &options)?;
+            if let Some(merge_target) = &gdal_merge_target {
+                merge_target.check_compatible(&schema)?;
+            }
+            if !schema.skipped_fields.is_empty() {
+                info!(
+                    "Skipping unsupported fields {:?} of layer {}",
+                    schema.skipped_fields, schema.source_layer
+                );
+            }
+            if schema.geometry_source == GeometrySource::Layer
+                && source_layer.defn().geom_fields().count() == 0
+            {
+                warn!(
+                    "Layer {} has no geometry and no coordinate columns were found",
+                    schema.source_layer
+                );
+            }
+
+            let mut report = IngestReport::new(&schema.source_layer, schema.skipped_fields.clone());
+            report.encoding = datasets[dataset_index].encoding.clone();
+            let transform = storage_transform(&source_layer, &schema, storage_srid)?;
+            let encoder = RowEncoder::new(&schema, storage_srid, transform);
+            let source_srid = schema.srid;
+            let fields = schema.columns.clone();
+            message_sender
+                .blocking_send(IngestMessage::Table(schema))
+                .map_err(|_| anyhow!("Channel closed unexpectedly"))?;
+
+            read_features(
+                &mut source_layer,
+                &encoder,
+                &message_sender,
+                &mut report,
+                &gdal_progress,
+            )?;
+            info!(
+                "Processed {} features for layer {}, {} skipped",
+                report.features_read, source_names[index], report.features_skipped
+            );
+
+            loaded.push(LoadedLayer {
+                layer_id: target_id,
+                source_layer: source_names[index].clone(),
+                table_name,
+                source_srid,
+                srid: storage_srid,
+                // Filled in from the loaded table
+                bbox: None,
+                geometry_type: None,
+                fields,
+                feature_count: report.features_loaded,
+                report,
+                version: None,
+            });
+        }
+
+        if loaded.iter().all(|layer| layer.feature_count == 0) {
+            return Err(InvalidUpload("No features found in dataset".to_string()).into());
+        }
+
+        Ok(loaded)
+    });
+
+    // Start a transaction for database operations
+    let mut tx = postgis_connector
+        .pool
+        .begin()
+        .await
+        .map_err(|e| anyhow!("Failed to start transaction: {}", e))?;
+
+    let db_result = async {
+        let mut loaded_tables = Vec::new();
+        let mut next_message = message_receiver.recv().await;
+
+        while let Some(IngestMessage::Table(schema)) = next_message {
+            progress.set_stage(IngestStage::Loading);
+
+            // Replace any table left over from an earlier attempt
+            sqlx::query(&format!("DROP TABLE IF EXISTS {}", schema.qualified_name()))
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| anyhow!("Failed to drop existing layer table: {}", e))?;
+            sqlx::query(&schema.create_table_sql(storage_srid))
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| anyhow!("Failed to create layer table: {}", e))?;
+
+            let mut copy = tx
+                .copy_in_raw(&schema.copy_sql())
+                .await
+                .map_err(|e| anyhow!("Failed to start COPY: {}", e))?;
+
+            // Stream rows until the next source layer starts or the file ends
+            next_message = loop {
+                match message_receiver.recv().await {
+                    Some(IngestMessage::Rows(rows, count)) => {
+                        if let Err(e) = copy.send(rows.into_bytes()).await {
+                            let _ = copy.abort("Failed to send COPY data").await;
+                            bail!("Failed to send features: {}", e);
+                        }
+                        progress.record_inserted(count);
+                    }
+                    other => break other,
+                }
+            };
+
+            // The channel closes when the GDAL task returns, its result is
+            // checked below before committing
+            let inserted = copy
+                .finish()
+                .await
+                .map_err(|e| anyhow!("Failed to load features: {}", e))?;
+
+            progress.set_stage(IngestStage::Validating);
+            let validation = validate_geometries(&mut tx, &schema, geometry_policy).await?;
+            sqlx::query(&schema.drop_source_fid_sql())
+                .execute(&mut *tx)
+                .await?;
+            progress.record_removed(validation.skipped);
+            info!(
+                "Loaded {} features into table {}, {} invalid geometries",
+                inserted, schema.table_name, validation.invalid
+            );
+
+            // Appended and upserted versions copy the indexes of the active
+            // version, a replacement is indexed like a new layer
+            let merged = match &merge_target {
+                Some(merge_target) => {
+                    progress.set_stage(IngestStage::Merging);
+                    Some(merge_target.merge(&mut tx, &schema).await?)
+                }
+                None => None,
+            };
+            let table_schema = merged.as_ref().map_or(&schema, |merged| &merged.schema);
+            let stats = table_stats(&mut tx, table_schema).await?;
+
+            let indexed_fields = match merge_target.as_ref().map(|target| target.mode) {
+                None | Some(IngestMode::Replace) => {
+                    progress.set_stage(IngestStage::Indexing);
+                    create_indexes(&mut tx, table_schema, &index_fields).await?
+                }
+                Some(_) => Vec::new(),
+            };
+
+            loaded_tables.push(LoadedTable {
+                inserted,
+                validation,
+                stats,
+                indexed_fields,
+                merged,
+            });
+        }
+
+        Ok(loaded_tables)
+    }
+    .await;
+
+    // Wait for the GDAL task, which stops at its next send once the
+    // receiver is gone if loading failed
+    drop(message_receiver);
+    let gdal_result = gdal_handle.await;
+
+    // Extracted archive contents are only needed while reading
+    if let Err(e) = tokio::fs::remove_dir_all(&work_dir).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove extracted archive {:?}: {}", work_dir, e);
+    }
+
+    // Check if database operations failed
+    let loaded_tables = match db_result {
+        Ok(loaded_tables) => loaded_tables,
+        Err(db_error) => {
+            let _ = tx.rollback().await;
+            return Err(db_error);
+        }
+    };
+
+    // Handle any errors from the GDAL processing
+    let mut loaded = match gdal_result {
+        Err(join_error) => {
+            let _ = tx.rollback().await;
+            return Err(anyhow!("GDAL processing task failed: {}", join_error));
+        }
+        Ok(Err(gdal_error)) => {
+            let _ = tx.rollback().await;
+            return Err(gdal_error);
+        }
+        Ok(Ok(loaded)) => loaded,
+    };
+
+    let read_counts: Vec<u64> = loaded.iter().map(|layer| layer.feature_count).collect();
+    let inserted_counts: Vec<u64> = loaded_tables.iter().map(|table| table.inserted).collect();
+    if inserted_counts != read_counts {
+        let _ = tx.rollback().await;
+        bail!(
+            "Loaded {:?} features but read {:?} from the file",
+            inserted_counts,
+            read_counts
+        );
+    }
+
+    for (loaded_layer, table) in loaded.iter_mut().zip(loaded_tables) {
+        loaded_layer
+            .report
+            .record_geometry_validation(table.validation);
+        loaded_layer.feature_count = loaded_layer.report.features_loaded;
+        loaded_layer.bbox = table.stats.bbox;
+        loaded_layer.geometry_type = table.stats.geometry_type;
+        loaded_layer.report.indexed_fields = table.indexed_fields;
+
+        // The target layer now describes its new version
+        if let (Some(merge_target), Some(merged)) = (&merge_target, table.merged) {
+            loaded_layer.layer_id = merge_target.layer_id;
+            loaded_layer.table_name = merge_target.table_name.clone();
+            loaded_layer.fields = merged.schema.columns;
+            loaded_layer.feature_count = merged.feature_count;
+            loaded_layer.version = Some(merged.version);
+        }
+    }
+
+    // A merge records its version under the target's lock, which is only
+    // released once the data side has committed
+    if let (Some(merge_target), Some(target_lock)) = (&merge_target, target_lock.as_mut()) {
+        for loaded_layer in loaded
+            .iter()
+            .filter(|loaded_layer| loaded_layer.layer_id == merge_target.layer_id)
+        {
+            if let Err(e) = merge_target
+                .record_version(target_lock, layer.id, loaded_layer)
+                .await
+            {
+                let _ = tx.rollback().await;
+                return Err(e);
+            }
+        }
+    }
+
+    // Commit the transaction
+    tx.commit()
+        .await
+        .map_err(|e| anyhow!("Failed to commit transaction: {}", e))?;
+    if let Some(target_lock) = target_lock {
+        target_lock
+            .commit()
+            .await
+            .map_err(|e| anyhow!("Failed to record the new version: {}", e))?;
+    }
+
+    progress.finish();
+
+    let outcome = IngestOutcome { layers: loaded };
+    info!(
+        "Successfully loaded {} features from {} source layers for layer {}",
+        outcome.feature_count(),
+        outcome.layers.len(),
+        layer.id
+    );
+
+    Ok(outcome)
+}
+
+/// Open the source datasets. Files from an archive that GDAL cannot open,
+/// such as stray metadata JSON, are skipped. Shapefiles are read with the
+/// `requested` encoding, or the one declared or detected for them.
+fn open_sources(
+    sources: &[PathBuf],
+    from_archive: bool,
+    requested: Option<&str>,
+) -> Result<Vec<SourceDataset>> {
+    let mut datasets = Vec::with_capacity(sources.len());
+    for source in sources {
+        let encoding = resolve_encoding(source, requested)?;
+        if let Some(encoding) = &encoding
+            && encoding.origin == EncodingOrigin::Detected
+        {
+            info!("Reading {:?} as {}", source, encoding.encoding);
+        }
+
+        let opened = match encoding.as_ref().and_then(SourceEncoding::open_option) {
+            Some(open_option) => Dataset::open_ex(
+                source,
+                DatasetOptions {
+                    open_flags: GdalOpenFlags::GDAL_OF_VECTOR,
+                    open_options: Some(&[open_option.as_str()]),
+                    ..Default::default()
+                },
+            )
+            .map_err(|e| e.to_string()),
+            None => gridwalk_core::file_utils::open_dataset(source).map_err(|e| e.to_string()),
+        };
+
+        match opened {
+            Ok(dataset) => datasets.push(SourceDataset { dataset, encoding }),
+            Err(e) if from_archive => {
+                warn!(
+                    "Skipping {:?} in archive, it cannot be opened: {}",
+                    source, e
+                );
+            }
+            Err(e) => {
+                return Err(
+                    InvalidUpload(format!("Failed to open uploaded dataset: {}", e)).into(),
+                );
+            }
+        }
+    }
+
+    if datasets.is_empty() {
+        return Err(InvalidUpload(
+            "Archive does not contain any dataset that can be opened".to_string(),
+        )
+        .into());
+    }
+    if requested.is_some() && datasets.iter().all(|source| source.encoding.is_none()) {
+        warn!("The encoding option only applies to shapefiles and was ignored");
+    }
+    Ok(datasets)
+}
+
+/// Indexes of the source layers to import. Every layer is imported unless
+/// the client asked for specific layers by name.
+fn select_source_layers(
+    source_names: &[String],
+    requested: Option<&[String]>,
+) -> Result<Vec<usize>> {
+    if source_names.is_empty() {
+        return Err(InvalidUpload("Dataset contains no layers".to_string()).into());
+    }
+
+    let Some(requested) = requested else {
+        return Ok((0..source_names.len()).collect());
+    };
+
+    for name in requested {
+        if !source_names.contains(name) {
+            return Err(InvalidUpload(format!(
+                "Layer '{}' not found in dataset, available layers: {}",
+                name,
+                source_names.join(", ")
+            ))
+            .into());
+        }
+    }
+
+    Ok((0..source_names.len())
+        .filter(|index| requested.contains(&source_names[*index]))
+        .collect())
+}
+
+/// Encode every feature of a source layer and send the rows in batches.
+/// Features that cannot be encoded are counted in the report and skipped.
+fn read_features<L: LayerAccess>(
+    source_layer: &mut L,
+    encoder: &RowEncoder,
+    message_sender: &mpsc::Sender<IngestMessage>,
+    report: &mut IngestReport,
+    progress: &ProgressTracker,
+) -> Result<()> {
+    let mut batch = String::new();
+    let mut batch_rows = 0;
+
+    for feature in source_layer.features() {
+        let feature_index = report.features_read;
+        report.features_read += 1;
+        let feature_id = feature.fid().unwrap_or(report.features_read);
+
+        match encoder
+            .encode(&feature, feature_id, &mut batch)
+            .map_err(|e| FeatureError {
+                source_layer: report.source_layer.clone(),
+                feature_index,
+                message: e.to_string(),
+            })? {
+            Encoded::Row { cast_failures } => {
+                for reason in cast_failures {
+                    report.record_cast_failure(feature_id, reason);
+                }
+                report.features_loaded += 1;
+                batch_rows += 1;
+                progress.record_read(false);
+            }
+            Encoded::Skipped(reason) => {
+                report.record_skipped(feature_id, reason);
+                progress.record_read(true);
+            }
+        }
+
+        if batch_rows == COPY_BATCH_SIZE {
+            message_sender
+                .blocking_send(IngestMessage::Rows(
+                    std::mem::take(&mut batch),
+                    batch_rows as u64,
+                ))
+                .map_err(|_| anyhow!("Channel closed unexpectedly"))?;
+            batch_rows = 0;
+        }
+    }
+
+    if batch_rows > 0 {
+        message_sender
+            .blocking_send(IngestMessage::Rows(batch, batch_rows as u64))
+            .map_err(|_| anyhow!("Channel closed unexpectedly"))?;
+    }
+
+    Ok(())
+}
+
+/// PostGIS connector of the connection database.
+fn postgis_connector(state: &AppState) -> Result<&PostgisConnector> {
+    let vector_connector = state
+        .connection
+        .as_vector()
+        .ok_or_else(|| anyhow!("Connection is not a vector connector"))?;
+
+    vector_connector
+        .as_any()
+        .downcast_ref::<PostgisConnector>()
+        .ok_or_else(|| anyhow!("Vector connector is not a PostGIS connector"))
+}
+
+/// Compute the hex encoded SHA-256 digest of an uploaded file.
+pub async fn file_checksum(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Drop a layer's data table from the connection database. Versioned
+/// layers lose their view and every version table.
+pub async fn drop_layer_table(state: &AppState, table_name: &str) -> Result<()> {
+    let postgis_connector = postgis_connector(state)?;
+    let mut tx = postgis_connector.pool.begin().await?;
+
+    let kind: Option<i8> = sqlx::query_scalar(
+        "SELECT c.relkind::\"char\" FROM pg_class c \
+         JOIN pg_namespace n ON n.oid = c.relnamespace \
+         WHERE n.nspname = $1 AND c.relname = $2",
+    )
+    .bind(&state.layer_schema)
+    .bind(table_name)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let mut tables = Vec::new();
+    if kind == Some(b'v' as i8) {
+        sqlx::query(&format!(
+            "DROP VIEW {}.{}",
+            quote_ident(&state.layer_schema),
+            quote_ident(table_name)
+        ))
+        .execute(&mut *tx)
+        .await?;
+    } else {
+        tables.push(table_name.to_string());
+    }
+
+    let version_prefix = format!("{}_v", table_name);
+    let version_tables: Vec<String> = sqlx::query_scalar(
+        "SELECT tablename::text FROM pg_tables \
+         WHERE schemaname = $1 AND starts_with(tablename, $2) \
+         AND substr(tablename, $3) ~ '^[0-9]+$'",
+    )
+    .bind(&state.layer_schema)
+    .bind(&version_prefix)
+    .bind(version_prefix.len() as i32 + 1)
+    .fetch_all(&mut *tx)
+    .await?;
+    tables.extend(version_tables);
+
+    for table in &tables {
+        sqlx::query(&format!(
+            "DROP TABLE IF EXISTS {}.{}",
+            quote_ident(&state.layer_schema),
+            quote_ident(table)
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    info!("Dropped layer table {}.{}", state.layer_schema, table_name);
+    Ok(())
+}
+
+/// Quote a Postgres identifier, escaping embedded double quotes.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
@@ -0,0 +1,183 @@
+use super::{FieldMapping, GeometryPolicy, IngestMode, normalize_encoding};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Client supplied options controlling how an upload is ingested, taken
+/// from the Upload-Metadata header when the upload is created.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IngestOptions {
+    /// Names of the source layers to import, every layer when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layers: Option<Vec<String>>,
+    /// Column holding the x coordinate (longitude, easting) of tabular files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_field: Option<String>,
+    /// Column holding the y coordinate (latitude, northing) of tabular files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y_field: Option<String>,
+    /// Column holding WKT geometries of tabular files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wkt_field: Option<String>,
+    /// EPSG code of the source coordinates, overriding the file's CRS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub srid: Option<i32>,
+    /// What to do with invalid geometries
+    #[serde(default)]
+    pub geometry_policy: GeometryPolicy,
+    /// Fields to index besides the geometry, by source or column name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_fields: Option<Vec<String>>,
+    /// Whether the upload creates a layer or updates `target_layer_id`
+    #[serde(default)]
+    pub mode: IngestMode,
+    /// Existing layer loaded into by the append, replace and upsert modes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_layer_id: Option<Uuid>,
+    /// Field identifying features in upsert mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_field: Option<String>,
+    /// Renames, casts, exclusions and defaults for source fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_mapping: Option<FieldMapping>,
+    /// Character encoding of shapefile attributes, overriding the declared
+    /// or detected one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+impl IngestOptions {
+    /// Apply an Upload-Metadata key. Unknown keys are ignored, invalid values
+    /// return a message for the client.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "layers" => {
+                // Comma separated names of the source layers to import
+                let layers: Vec<String> = value
+                    .split(',')
+                    .map(|layer| layer.trim().to_string())
+                    .filter(|layer| !layer.is_empty())
+                    .collect();
+                if layers.is_empty() {
+                    return Err("Layers must name at least one source layer".to_string());
+                }
+                self.layers = Some(layers);
+            }
+            "index_fields" => {
+                // Comma separated names of the fields to index
+                let fields: Vec<String> = value
+                    .split(',')
+                    .map(|field| field.trim().to_string())
+                    .filter(|field| !field.is_empty())
+                    .collect();
+                if fields.is_empty() {
+                    return Err("index_fields must name at least one field".to_string());
+                }
+                self.index_fields = Some(fields);
+            }
+            "mode" => {
+                self.mode = value.parse().map_err(|_| {
+                    "Mode must be 'create', 'append', 'replace' or 'upsert'".to_string()
+                })?;
+            }
+            "target_layer_id" => {
+                let target_layer_id = value
+                    .parse()
+                    .map_err(|_| "target_layer_id must be a valid UUID".to_string())?;
+                self.target_layer_id = Some(target_layer_id);
+            }
+            "key_field" => self.key_field = Some(field_name(key, value)?),
+            "x_field" => self.x_field = Some(field_name(key, value)?),
+            "y_field" => self.y_field = Some(field_name(key, value)?),
+            "wkt_field" => self.wkt_field = Some(field_name(key, value)?),
+            "srid" => {
+                let srid = value
+                    .parse()
+                    .ok()
+                    .filter(|srid| *srid > 0)
+                    .ok_or_else(|| "SRID must be a positive integer".to_string())?;
+                self.srid = Some(srid);
+            }
+            "geometry_policy" => {
+                self.geometry_policy = value.parse().map_err(|_| {
+                    "Geometry policy must be 'reject', 'repair' or 'skip'".to_string()
+                })?;
+            }
+            "field_mapping" => self.field_mapping = Some(FieldMapping::parse(value)?),
+            "encoding" => self.encoding = Some(normalize_encoding(value)?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check options that only make sense together.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.x_field.is_some() != self.y_field.is_some() {
+            return Err("x_field and y_field must be given together".to_string());
+        }
+        if self.wkt_field.is_some() && self.x_field.is_some() {
+            return Err("wkt_field cannot be combined with x_field and y_field".to_string());
+        }
+        if (self.mode == IngestMode::Create) == self.target_layer_id.is_some() {
+            return Err(
+                "target_layer_id must be given with the append, replace and upsert modes only"
+                    .to_string(),
+            );
+        }
+        if (self.mode == IngestMode::Upsert) != self.key_field.is_some() {
+            return Err("key_field must be given with the upsert mode only".to_string());
+        }
+        Ok(())
+    }
+
+    /// The options as Upload-Metadata key and value pairs.
+    pub fn metadata(&self) -> Vec<(&'static str, String)> {
+        let mut metadata = Vec::new();
+        if let Some(layers) = &self.layers {
+            metadata.push(("layers", layers.join(",")));
+        }
+        if let Some(x_field) = &self.x_field {
+            metadata.push(("x_field", x_field.clone()));
+        }
+        if let Some(y_field) = &self.y_field {
+            metadata.push(("y_field", y_field.clone()));
+        }
+        if let Some(wkt_field) = &self.wkt_field {
+            metadata.push(("wkt_field", wkt_field.clone()));
+        }
+        if let Some(srid) = self.srid {
+            metadata.push(("srid", srid.to_string()));
+        }
+        if self.geometry_policy != GeometryPolicy::default() {
+            metadata.push(("geometry_policy", self.geometry_policy.to_string()));
+        }
+        if let Some(index_fields) = &self.index_fields {
+            metadata.push(("index_fields", index_fields.join(",")));
+        }
+        if self.mode != IngestMode::default() {
+            metadata.push(("mode", self.mode.to_string()));
+        }
+        if let Some(target_layer_id) = self.target_layer_id {
+            metadata.push(("target_layer_id", target_layer_id.to_string()));
+        }
+        if let Some(key_field) = &self.key_field {
+            metadata.push(("key_field", key_field.clone()));
+        }
+        if let Some(field_mapping) = &self.field_mapping
+            && let Ok(json) = serde_json::to_string(field_mapping)
+        {
+            metadata.push(("field_mapping", json));
+        }
+        if let Some(encoding) = &self.encoding {
+            metadata.push(("encoding", encoding.clone()));
+        }
+        metadata
+    }
+}
+
+fn field_name(key: &str, value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err(format!("{} must not be empty", key));
+    }
+    Ok(value.to_string())
+}
@@ -0,0 +1,406 @@
+use super::copy::{Encoded, RowEncoder};
+use super::reproject::storage_transform;
+use super::{
+    ColumnSchema, FeatureError, FeatureIssue, GeometryPolicy, GeometrySource, IngestOptions,
+    InvalidUpload, MAX_ARCHIVE_EXPANSION, MergeTarget, SourceEncoding, archive,
+    extract_layer_schema, open_sources, select_source_layers, source_srid,
+};
+use crate::config::AppState;
+use anyhow::{Result, anyhow};
+use gdal::vector::LayerAccess;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Number of features read from each layer to check their geometries
+const SAMPLE_SIZE: u64 = 1000;
+
+/// Number of problem features listed per layer
+const MAX_SAMPLES: usize = 20;
+
+/// What a dry run learned about an uploaded file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PreflightReport {
+    /// Whether an ingest with the same options is expected to succeed
+    pub valid: bool,
+    /// The file is an archive of one or more datasets
+    pub archive: bool,
+    pub layers: Vec<LayerPreflight>,
+    /// Problems with the file as a whole that would fail the ingest
+    pub errors: Vec<String>,
+}
+
+/// What a dry run learned about one source layer
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LayerPreflight {
+    pub name: String,
+    /// Short name of the GDAL driver that read the layer, e.g. `GPKG`
+    pub driver: String,
+    /// Encoding the attribute text would be read with, for shapefiles
+    pub encoding: Option<SourceEncoding>,
+    /// Whether the layer would be loaded with the given options
+    pub selected: bool,
+    pub geometry_source: Option<GeometrySource>,
+    /// PostGIS geometry type name of the sampled features, `GEOMETRY` when
+    /// types are mixed
+    pub geometry_type: Option<String>,
+    /// EPSG code of the source coordinates
+    pub srid: Option<i32>,
+    /// Name of the layer's coordinate reference system, also given when it
+    /// has no EPSG code
+    pub crs_name: Option<String>,
+    /// Count reported by the driver, or counted when the sample covers the
+    /// whole layer
+    pub feature_count: Option<u64>,
+    pub fields: Vec<ColumnSchema>,
+    /// Source fields with a type that cannot be stored
+    pub skipped_fields: Vec<String>,
+    pub features_sampled: u64,
+    /// Sampled features that would be left out
+    pub features_skipped: u64,
+    /// Sampled features with an invalid geometry
+    pub invalid_geometries: u64,
+    /// Sampled values that could not be cast to their mapped type
+    pub cast_failures: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_samples: Vec<FeatureIssue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invalid_samples: Vec<FeatureIssue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cast_failure_samples: Vec<FeatureIssue>,
+    pub warnings: Vec<String>,
+    /// Problems that would fail the ingest if the layer is selected
+    pub errors: Vec<String>,
+}
+
+/// Where the layers of an upload would be loaded
+struct Destination {
+    /// Database schema the tables would be created in
+    layer_schema: String,
+    /// EPSG code the geometries would be stored in
+    storage_srid: i32,
+    /// Existing layer the upload would be merged into
+    merge_target: Option<MergeTarget>,
+}
+
+/// Inspect an uploaded file the way an ingest with `options` would read it,
+/// without creating any table. Every layer is described and a sample of its
+/// features is encoded to find geometries that would be skipped or fail
+/// validation. Problems with the file itself are part of the report, only
+/// unexpected failures are returned as errors. Archives are extracted into
+/// `work_dir`, which is removed afterwards. `upload_type` names the format
+/// of files GDAL only recognises by extension, such as CSV.
+pub async fn preflight_upload(
+    state: &AppState,
+    upload_path: PathBuf,
+    upload_type: Option<String>,
+    work_dir: PathBuf,
+    options: IngestOptions,
+) -> Result<PreflightReport> {
+    let max_extracted_size = (state.max_upload_size as u64).saturating_mul(MAX_ARCHIVE_EXPANSION);
+
+    // Rows merged into an existing layer are stored in its SRID
+    let merge_target = match MergeTarget::load(state, &options).await {
+        Ok(merge_target) => merge_target,
+        Err(e) if e.is::<InvalidUpload>() => {
+            return Ok(PreflightReport {
+                errors: vec![e.to_string()],
+                ..Default::default()
+            });
+        }
+        Err(e) => return Err(e),
+    };
+    let destination = Destination {
+        layer_schema: state.layer_schema.clone(),
+        storage_srid: merge_target
+            .as_ref()
+            .map_or(state.ingest_config.storage_srid, |target| target.srid),
+        merge_target,
+    };
+
+    let gdal_work_dir = work_dir.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        preflight_file(
+            &upload_path,
+            upload_type.as_deref(),
+            &gdal_work_dir,
+            max_extracted_size,
+            &options,
+            &destination,
+        )
+    })
+    .await
+    .map_err(|e| anyhow!("Preflight task panicked: {}", e));
+
+    if let Err(e) = tokio::fs::remove_dir_all(&work_dir).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove extracted archive {:?}: {}", work_dir, e);
+    }
+
+    report?
+}
+
+fn preflight_file(
+    path: &Path,
+    upload_type: Option<&str>,
+    work_dir: &Path,
+    max_extracted_size: u64,
+    options: &IngestOptions,
+    destination: &Destination,
+) -> Result<PreflightReport> {
+    let mut report = PreflightReport::default();
+    match inspect_file(
+        path,
+        upload_type,
+        work_dir,
+        max_extracted_size,
+        options,
+        destination,
+        &mut report,
+    ) {
+        Ok(()) => {}
+        Err(e) if e.is::<InvalidUpload>() => report.errors.push(e.to_string()),
+        Err(e) => return Err(e),
+    }
+
+    report.valid = report.errors.is_empty()
+        && report
+            .layers
+            .iter()
+            .all(|layer| !layer.selected || layer.errors.is_empty());
+    Ok(report)
+}
+
+fn inspect_file(
+    path: &Path,
+    upload_type: Option<&str>,
+    work_dir: &Path,
+    max_extracted_size: u64,
+    options: &IngestOptions,
+    destination: &Destination,
+    report: &mut PreflightReport,
+) -> Result<()> {
+    report.archive = archive::detect_archive(path)?.is_some();
+    let sources = archive::prepare_sources(path, upload_type, work_dir, max_extracted_size)?;
+    let datasets = open_sources(&sources, report.archive, options.encoding.as_deref())?;
+
+    let source_names: Vec<String> = datasets
+        .iter()
+        .flat_map(|source| {
+            source
+                .dataset
+                .layers()
+                .map(|source_layer| source_layer.name())
+        })
+        .collect();
+    let selected = select_source_layers(&source_names, options.layers.as_deref())?;
+    if destination.merge_target.is_some() && selected.len() != 1 {
+        report.errors.push(format!(
+            "Uploads into an existing layer must have a single source layer, \
+             use 'layers' to pick one of: {}",
+            source_names.join(", ")
+        ));
+    }
+
+    let mut index = 0;
+    for source in &datasets {
+        let driver = source.dataset.driver().short_name();
+        for mut source_layer in source.dataset.layers() {
+            let mut layer = inspect_layer(
+                &mut source_layer,
+                &driver,
+                selected.contains(&index),
+                options,
+                destination,
+            )?;
+            layer.encoding = source.encoding.clone();
+            report.layers.push(layer);
+            index += 1;
+        }
+    }
+
+    if report
+        .layers
+        .iter()
+        .filter(|layer| layer.selected)
+        .all(|layer| layer.feature_count == Some(0))
+    {
+        report
+            .errors
+            .push("No features found in dataset".to_string());
+    }
+    Ok(())
+}
+
+/// Describe one source layer and check a sample of its features.
+fn inspect_layer<L: LayerAccess>(
+    source_layer: &mut L,
+    driver: &str,
+    selected: bool,
+    options: &IngestOptions,
+    destination: &Destination,
+) -> Result<LayerPreflight> {
+    let name = source_layer.name();
+    let mut preflight = LayerPreflight {
+        name: name.clone(),
+        driver: driver.to_string(),
+        selected,
+        srid: source_srid(source_layer),
+        crs_name: source_layer
+            .spatial_ref()
+            .and_then(|spatial_ref| spatial_ref.name().ok()),
+        feature_count: source_layer.try_feature_count(),
+        ..Default::default()
+    };
+
+    let schema = match extract_layer_schema(source_layer, &destination.layer_schema, &name, options)
+    {
+        Ok(schema) => schema,
+        Err(e) if e.is::<InvalidUpload>() => {
+            preflight.errors.push(e.to_string());
+            return Ok(preflight);
+        }
+        Err(e) => return Err(e),
+    };
+    preflight.srid = schema.srid;
+    preflight.geometry_source = Some(schema.geometry_source.clone());
+    preflight.fields = schema.columns.clone();
+    preflight.skipped_fields = schema.skipped_fields.clone();
+
+    if !schema.skipped_fields.is_empty() {
+        preflight.warnings.push(format!(
+            "Fields with unsupported types will not be loaded: {}",
+            schema.skipped_fields.join(", ")
+        ));
+    }
+    if schema.geometry_source == GeometrySource::Layer
+        && source_layer.defn().geom_fields().count() == 0
+    {
+        preflight
+            .warnings
+            .push("Layer has no geometry and no coordinate columns were found".to_string());
+    }
+
+    if let Some(merge_target) = &destination.merge_target {
+        match merge_target.check_compatible(&schema) {
+            Ok(()) => {}
+            Err(e) if e.is::<InvalidUpload>() => preflight.errors.push(e.to_string()),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // A layer that cannot be reprojected is still sampled as it is
+    let transform = match storage_transform(source_layer, &schema, destination.storage_srid) {
+        Ok(transform) => transform,
+        Err(e) if e.is::<InvalidUpload>() => {
+            preflight.errors.push(e.to_string());
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let encoder = RowEncoder::new(&schema, destination.storage_srid, transform);
+
+    let mut geometry_types = BTreeSet::new();
+    let mut row = String::new();
+    let mut sample_complete = true;
+    for feature in source_layer.features().take(SAMPLE_SIZE as usize) {
+        let feature_index = preflight.features_sampled;
+        preflight.features_sampled += 1;
+        let feature_id = feature.fid().unwrap_or(preflight.features_sampled);
+
+        row.clear();
+        match encoder.encode(&feature, feature_id, &mut row) {
+            Ok(Encoded::Row { cast_failures }) => {
+                for reason in cast_failures {
+                    preflight.cast_failures += 1;
+                    if preflight.cast_failure_samples.len() < MAX_SAMPLES {
+                        preflight
+                            .cast_failure_samples
+                            .push(FeatureIssue { feature_id, reason });
+                    }
+                }
+            }
+            Ok(Encoded::Skipped(reason)) => {
+                preflight.features_skipped += 1;
+                if preflight.skipped_samples.len() < MAX_SAMPLES {
+                    preflight
+                        .skipped_samples
+                        .push(FeatureIssue { feature_id, reason });
+                }
+                continue;
+            }
+            Err(e) => {
+                let error = FeatureError {
+                    source_layer: name.clone(),
+                    feature_index,
+                    message: e.to_string(),
+                };
+                preflight.errors.push(error.to_string());
+                sample_complete = false;
+                break;
+            }
+        }
+
+        if let Ok(Some(geometry)) = encoder.source_geometry(&feature)
+            && !geometry.is_empty()
+        {
+            geometry_types.insert(geometry.geometry_name());
+            if !geometry.is_valid() {
+                preflight.invalid_geometries += 1;
+                if preflight.invalid_samples.len() < MAX_SAMPLES {
+                    preflight.invalid_samples.push(FeatureIssue {
+                        feature_id,
+                        reason: "Invalid geometry".to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    if sample_complete && preflight.features_sampled < SAMPLE_SIZE {
+        preflight.feature_count = Some(preflight.features_sampled);
+    }
+    preflight.geometry_type = match geometry_types.len() {
+        0 => None,
+        1 => geometry_types.pop_first(),
+        _ => Some("GEOMETRY".to_string()),
+    };
+
+    if preflight.feature_count == Some(0) {
+        preflight.warnings.push("Layer has no features".to_string());
+    }
+    if preflight.features_skipped > 0 {
+        preflight.warnings.push(format!(
+            "{} of {} sampled features would be skipped",
+            preflight.features_skipped, preflight.features_sampled
+        ));
+    }
+    if preflight.cast_failures > 0 {
+        preflight.warnings.push(format!(
+            "{} values of the sampled features cannot be cast and would be stored as the field default or NULL",
+            preflight.cast_failures
+        ));
+    }
+    if preflight.invalid_geometries > 0 {
+        let message = format!(
+            "{} of {} sampled features have invalid geometries",
+            preflight.invalid_geometries, preflight.features_sampled
+        );
+        match options.geometry_policy {
+            GeometryPolicy::Reject => preflight.errors.push(format!(
+                "{}, the upload would be rejected with geometry_policy 'reject'",
+                message
+            )),
+            GeometryPolicy::Repair => preflight
+                .warnings
+                .push(format!("{}, they would be repaired", message)),
+            GeometryPolicy::Skip => preflight
+                .warnings
+                .push(format!("{}, they would be left out", message)),
+        }
+    }
+
+    Ok(preflight)
+}
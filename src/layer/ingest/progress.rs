@@ -0,0 +1,161 @@
+use crate::layer::Layer;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use strum_macros::Display;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Steps of an ingest, in order
+#[derive(Debug, Clone, Copy, PartialEq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum IngestStage {
+    /// Unpacking an uploaded archive
+    Extracting,
+    /// Reading features and copying them into the layer tables
+    Loading,
+    /// Checking geometries and computing the layer extent
+    Validating,
+    /// Moving the loaded features into an existing layer
+    Merging,
+    /// Building spatial and attribute indexes
+    Indexing,
+    Complete,
+}
+
+/// Progress of a running ingest, stored on the layer for clients to poll
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IngestProgress {
+    pub stage: IngestStage,
+    /// Number of features in the selected layers, when the driver knows it
+    pub features_total: Option<u64>,
+    pub features_read: u64,
+    pub features_inserted: u64,
+    pub features_skipped: u64,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Shared progress of one ingest. Counters are written to the layer every
+/// `interval` features and whenever the stage changes.
+pub struct ProgressTracker {
+    sender: watch::Sender<IngestProgress>,
+    interval: u64,
+}
+
+impl ProgressTracker {
+    /// Start tracking an ingest, persisting updates in the background until
+    /// the tracker is dropped.
+    pub fn start(
+        app_db: Arc<PgPool>,
+        layer_id: Uuid,
+        interval: u64,
+    ) -> (Arc<Self>, JoinHandle<()>) {
+        let now = Utc::now();
+        let (sender, mut receiver) = watch::channel(IngestProgress {
+            stage: IngestStage::Loading,
+            features_total: None,
+            features_read: 0,
+            features_inserted: 0,
+            features_skipped: 0,
+            started_at: now,
+            updated_at: now,
+            finished_at: None,
+        });
+
+        // Writes are skipped while one is in flight, only the latest
+        // progress is stored
+        let persist_handle = tokio::spawn(async move {
+            while receiver.changed().await.is_ok() {
+                let progress = receiver.borrow_and_update().clone();
+                if let Err(e) = Layer::update_progress(layer_id, &progress, &*app_db).await {
+                    warn!(
+                        "Failed to store ingest progress for layer {}: {}",
+                        layer_id, e
+                    );
+                }
+            }
+        });
+
+        let tracker = ProgressTracker {
+            sender,
+            interval: interval.max(1),
+        };
+        (Arc::new(tracker), persist_handle)
+    }
+
+    /// The latest progress, including counts not stored yet.
+    pub fn current(&self) -> IngestProgress {
+        self.sender.borrow().clone()
+    }
+
+    pub fn set_stage(&self, stage: IngestStage) {
+        self.sender.send_modify(|progress| {
+            progress.stage = stage;
+            progress.updated_at = Utc::now();
+        });
+    }
+
+    pub fn set_total(&self, features_total: Option<u64>) {
+        self.sender.send_modify(|progress| {
+            progress.features_total = features_total;
+            progress.updated_at = Utc::now();
+        });
+    }
+
+    /// Count a feature read from the source, skipped or not.
+    pub fn record_read(&self, skipped: bool) {
+        let interval = self.interval;
+        self.sender.send_if_modified(|progress| {
+            progress.features_read += 1;
+            if skipped {
+                progress.features_skipped += 1;
+            }
+            let notify = progress.features_read % interval == 0;
+            if notify {
+                progress.updated_at = Utc::now();
+            }
+            notify
+        });
+    }
+
+    /// Count rows sent to the database.
+    pub fn record_inserted(&self, count: u64) {
+        let interval = self.interval;
+        self.sender.send_if_modified(|progress| {
+            let before = progress.features_inserted / interval;
+            progress.features_inserted += count;
+            let notify = progress.features_inserted / interval != before;
+            if notify {
+                progress.updated_at = Utc::now();
+            }
+            notify
+        });
+    }
+
+    /// Count features removed after loading, e.g. invalid geometries.
+    pub fn record_removed(&self, count: u64) {
+        if count == 0 {
+            return;
+        }
+        self.sender.send_modify(|progress| {
+            progress.features_inserted = progress.features_inserted.saturating_sub(count);
+            progress.features_skipped += count;
+            progress.updated_at = Utc::now();
+        });
+    }
+
+    pub fn finish(&self) {
+        self.sender.send_modify(|progress| {
+            let now = Utc::now();
+            progress.stage = IngestStage::Complete;
+            progress.updated_at = now;
+            progress.finished_at = Some(now);
+        });
+    }
+}
@@ -0,0 +1,81 @@
+use super::{GeometryValidation, SourceEncoding};
+use serde::{Deserialize, Serialize};
+
+/// Number of sample features kept per kind of issue
+const MAX_SAMPLES: usize = 20;
+
+/// A feature that was changed or left out during ingest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureIssue {
+    /// Feature id from the source, or its position when the driver has none
+    pub feature_id: u64,
+    pub reason: String,
+}
+
+/// What happened to one source layer during ingest, stored on its layer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IngestReport {
+    pub source_layer: String,
+    pub features_read: u64,
+    pub features_loaded: u64,
+    pub features_skipped: u64,
+    /// Source fields with a type that cannot be stored
+    #[serde(default)]
+    pub skipped_fields: Vec<String>,
+    /// Sample of the skipped features and why
+    #[serde(default)]
+    pub skipped_samples: Vec<FeatureIssue>,
+    /// Values that could not be cast to their mapped type and were stored
+    /// as the field's default, or NULL
+    #[serde(default)]
+    pub cast_failures: u64,
+    /// Sample of the values that could not be cast and the feature they
+    /// belong to
+    #[serde(default)]
+    pub cast_failure_samples: Vec<FeatureIssue>,
+    /// Invalid geometries found after loading and what was done with them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geometry_validation: Option<GeometryValidation>,
+    /// Attribute columns indexed after loading, besides the geometry
+    #[serde(default)]
+    pub indexed_fields: Vec<String>,
+    /// Encoding the attribute text was read with, for shapefiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<SourceEncoding>,
+}
+
+impl IngestReport {
+    pub fn new(source_layer: &str, skipped_fields: Vec<String>) -> Self {
+        IngestReport {
+            source_layer: source_layer.to_string(),
+            skipped_fields,
+            ..Default::default()
+        }
+    }
+
+    /// Count a feature that was left out, keeping the first few as samples.
+    pub fn record_skipped(&mut self, feature_id: u64, reason: String) {
+        self.features_skipped += 1;
+        if self.skipped_samples.len() < MAX_SAMPLES {
+            self.skipped_samples
+                .push(FeatureIssue { feature_id, reason });
+        }
+    }
+
+    /// Count a value that could not be cast, keeping the first few as
+    /// samples.
+    pub fn record_cast_failure(&mut self, feature_id: u64, reason: String) {
+        self.cast_failures += 1;
+        if self.cast_failure_samples.len() < MAX_SAMPLES {
+            self.cast_failure_samples
+                .push(FeatureIssue { feature_id, reason });
+        }
+    }
+
+    /// Add the result of geometry validation, counting features it removed.
+    pub fn record_geometry_validation(&mut self, validation: GeometryValidation) {
+        self.features_skipped += validation.skipped;
+        self.features_loaded = self.features_loaded.saturating_sub(validation.skipped);
+        self.geometry_validation = Some(validation);
+    }
+}
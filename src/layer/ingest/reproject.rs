@@ -0,0 +1,55 @@
+use super::{GeometrySource, InvalidUpload, TableSchema};
+use anyhow::{Result, anyhow};
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
+use gdal::vector::LayerAccess;
+
+/// Transformation from a source layer's CRS to the storage SRID, `None`
+/// when no reprojection is needed.
+///
+/// An `srid` from the upload metadata (or implied by longitude and latitude
+/// columns) wins over the file's CRS. Layers with geometry but no known CRS
+/// are rejected.
+pub fn storage_transform<L: LayerAccess>(
+    layer: &L,
+    schema: &TableSchema,
+    storage_srid: i32,
+) -> Result<Option<CoordTransform>> {
+    // Layers without geometry have nothing to reproject
+    if schema.geometry_source == GeometrySource::Layer && layer.defn().geom_fields().count() == 0 {
+        return Ok(None);
+    }
+
+    let mut source_ref = match schema.srid {
+        Some(srid) if srid == storage_srid => return Ok(None),
+        Some(srid) => epsg_spatial_ref(srid)?,
+        // A CRS GDAL could not match to an EPSG code can still be transformed
+        None => match (&schema.geometry_source, layer.spatial_ref()) {
+            (GeometrySource::Layer, Some(spatial_ref)) => spatial_ref,
+            _ => {
+                return Err(InvalidUpload(format!(
+                    "Layer '{}' has no coordinate reference system, set 'srid' in Upload-Metadata",
+                    schema.source_layer
+                ))
+                .into());
+            }
+        },
+    };
+    source_ref.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+
+    let mut target_ref = epsg_spatial_ref(storage_srid)?;
+    target_ref.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+
+    let transform = CoordTransform::new(&source_ref, &target_ref).map_err(|e| {
+        InvalidUpload(format!(
+            "Cannot reproject layer '{}' to EPSG:{}: {}",
+            schema.source_layer, storage_srid, e
+        ))
+    })?;
+    Ok(Some(transform))
+}
+
+fn epsg_spatial_ref(srid: i32) -> Result<SpatialRef> {
+    let code = u32::try_from(srid).map_err(|_| anyhow!("Invalid SRID {}", srid))?;
+    SpatialRef::from_epsg(code)
+        .map_err(|e| InvalidUpload(format!("Unknown SRID {}: {}", srid, e)).into())
+}
@@ -0,0 +1,267 @@
+use super::{
+    FieldType, GeometrySource, IngestOptions, InvalidUpload, detect_geometry_source, quote_ident,
+};
+use anyhow::Result;
+use gdal::vector::{LayerAccess, OGRFieldType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Primary key column added to every layer table
+pub const ID_COLUMN: &str = "id";
+
+/// Geometry column of every layer table
+pub const GEOMETRY_COLUMN: &str = "geom";
+
+/// Feature id in the source file, only kept while a table is being loaded
+/// so invalid geometries can be reported by the id the client knows
+pub const SOURCE_FID_COLUMN: &str = "gridwalk_source_fid";
+
+/// A source field and the table column it is loaded into
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnSchema {
+    pub source_name: String,
+    pub name: String,
+    pub pg_type: String,
+    /// Type the field mapping cast the source values to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cast: Option<FieldType>,
+    /// Value stored when the source value is null
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// Table layout for one source layer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableSchema {
+    /// Database schema the table is created in
+    pub data_schema: String,
+    pub table_name: String,
+    pub source_layer: String,
+    /// EPSG code of the source coordinates
+    pub srid: Option<i32>,
+    pub geometry_source: GeometrySource,
+    pub columns: Vec<ColumnSchema>,
+    /// Source fields with a type that cannot be stored
+    pub skipped_fields: Vec<String>,
+}
+
+/// Postgres column type for a GDAL field type, `None` when unsupported.
+pub fn pg_type_for_field(field_type: OGRFieldType::Type) -> Option<&'static str> {
+    match field_type {
+        OGRFieldType::OFTInteger => Some("integer"),
+        OGRFieldType::OFTInteger64 => Some("bigint"),
+        OGRFieldType::OFTReal => Some("double precision"),
+        OGRFieldType::OFTString => Some("text"),
+        OGRFieldType::OFTDate => Some("date"),
+        OGRFieldType::OFTDateTime => Some("timestamptz"),
+        OGRFieldType::OFTIntegerList
+        | OGRFieldType::OFTInteger64List
+        | OGRFieldType::OFTRealList
+        | OGRFieldType::OFTStringList => Some("jsonb"),
+        _ => None,
+    }
+}
+
+/// EPSG code of the layer's spatial reference, if it has one.
+pub fn source_srid<L: LayerAccess>(layer: &L) -> Option<i32> {
+    let mut spatial_ref = layer.spatial_ref()?;
+    if spatial_ref.auth_code().is_err() {
+        // Files such as shapefiles often carry a CRS without an authority code
+        spatial_ref.auto_identify_epsg().ok()?;
+    }
+    match spatial_ref.auth_name() {
+        Ok(name) if name == "EPSG" => spatial_ref.auth_code().ok(),
+        _ => None,
+    }
+}
+
+/// Turn a source field name into a safe, lowercase column name.
+fn column_name(source_name: &str) -> String {
+    let mut name: String = source_name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    // Postgres truncates identifiers to 63 bytes
+    name.truncate(63);
+    name
+}
+
+/// Build the table layout for a GDAL layer. Field names are sanitised and
+/// de-duplicated, and never collide with the id and geometry columns.
+/// The upload's field mapping can rename, cast or exclude fields and give
+/// them a default.
+pub fn extract_layer_schema<L: LayerAccess>(
+    layer: &L,
+    data_schema: &str,
+    table_name: &str,
+    options: &IngestOptions,
+) -> Result<TableSchema> {
+    let mapping = options.field_mapping.clone().unwrap_or_default();
+    let mut used_names: HashSet<String> = [
+        ID_COLUMN.to_string(),
+        GEOMETRY_COLUMN.to_string(),
+        SOURCE_FID_COLUMN.to_string(),
+    ]
+    .into();
+
+    // Names chosen in the mapping are reserved before any derived name
+    for field in layer.defn().fields() {
+        let Some(rename) = mapping
+            .rule(&field.name())
+            .and_then(|rule| rule.rename.as_ref())
+        else {
+            continue;
+        };
+        if !used_names.insert(column_name(rename)) {
+            return Err(InvalidUpload(format!(
+                "Field '{}' cannot be renamed to '{}', the column name is already used",
+                field.name(),
+                rename
+            ))
+            .into());
+        }
+    }
+
+    let mut columns = Vec::new();
+    let mut skipped_fields = Vec::new();
+
+    for field in layer.defn().fields() {
+        let source_name = field.name();
+        let rule = mapping.rule(&source_name).cloned().unwrap_or_default();
+        if rule.exclude {
+            continue;
+        }
+        let Some(pg_type) = pg_type_for_field(field.field_type()) else {
+            skipped_fields.push(source_name);
+            continue;
+        };
+        let pg_type = rule.cast.map_or(pg_type, FieldType::pg_type);
+
+        let name = match &rule.rename {
+            Some(rename) => column_name(rename),
+            None => {
+                let base_name = column_name(&source_name);
+                let mut name = base_name.clone();
+                let mut suffix = 1;
+                while used_names.contains(&name) {
+                    name = format!("{}_{}", base_name, suffix);
+                    suffix += 1;
+                }
+                used_names.insert(name.clone());
+                name
+            }
+        };
+
+        // Defaults are checked once here rather than on every feature
+        let default = match rule.default_text() {
+            Some(text) => {
+                let converted = FieldType::from_pg_type(pg_type)
+                    .and_then(|field_type| field_type.convert(&text))
+                    .ok_or_else(|| {
+                        InvalidUpload(format!(
+                            "Default '{}' of field '{}' is not a valid {}",
+                            text, source_name, pg_type
+                        ))
+                    })?;
+                Some(converted)
+            }
+            None => None,
+        };
+
+        columns.push(ColumnSchema {
+            source_name,
+            name,
+            pg_type: pg_type.to_string(),
+            cast: rule.cast,
+            default,
+        });
+    }
+
+    let field_names: Vec<String> = layer.defn().fields().map(|field| field.name()).collect();
+    let has_geometry = layer.defn().geom_fields().count() > 0;
+    let geometry_source = detect_geometry_source(&field_names, has_geometry, options)?;
+
+    // Explicit SRID metadata wins over the file's own CRS
+    let srid = options.srid.or_else(|| match geometry_source {
+        GeometrySource::Layer => source_srid(layer),
+        _ => geometry_source.implied_srid(),
+    });
+
+    Ok(TableSchema {
+        data_schema: data_schema.to_string(),
+        table_name: table_name.to_string(),
+        source_layer: layer.name(),
+        srid,
+        geometry_source,
+        columns,
+        skipped_fields,
+    })
+}
+
+impl TableSchema {
+    /// Qualified, quoted table name
+    pub fn qualified_name(&self) -> String {
+        format!(
+            "{}.{}",
+            quote_ident(&self.data_schema),
+            quote_ident(&self.table_name)
+        )
+    }
+
+    /// Statement creating the layer table, with geometries in `storage_srid`.
+    /// Every layer table has the same layout: an `id` BIGSERIAL primary key,
+    /// a `geom` column, then the mapped fields. While loading the table also
+    /// has a `gridwalk_source_fid` column, dropped once the geometries are
+    /// validated (see `drop_source_fid_sql`). Tiles (`layer_tile`,
+    /// `version_tile`), feature pages and merges read tables back relying on
+    /// it, which is why tiles are not served by the PostGIS connector's
+    /// `get_tile`, whose expected layout is not known here.
+    pub fn create_table_sql(&self, storage_srid: i32) -> String {
+        let mut definitions = vec![
+            format!("{} BIGSERIAL PRIMARY KEY", quote_ident(ID_COLUMN)),
+            format!(
+                "{} geometry(Geometry, {})",
+                quote_ident(GEOMETRY_COLUMN),
+                storage_srid
+            ),
+            format!("{} bigint", quote_ident(SOURCE_FID_COLUMN)),
+        ];
+        definitions.extend(
+            self.columns
+                .iter()
+                .map(|column| format!("{} {}", quote_ident(&column.name), column.pg_type)),
+        );
+        format!(
+            "CREATE TABLE {} ({})",
+            self.qualified_name(),
+            definitions.join(", ")
+        )
+    }
+
+    /// Statement starting a text format COPY of the geometry, source feature
+    /// id and field columns
+    pub fn copy_sql(&self) -> String {
+        let mut column_names = vec![quote_ident(GEOMETRY_COLUMN), quote_ident(SOURCE_FID_COLUMN)];
+        column_names.extend(self.columns.iter().map(|column| quote_ident(&column.name)));
+        format!(
+            "COPY {} ({}) FROM STDIN",
+            self.qualified_name(),
+            column_names.join(", ")
+        )
+    }
+
+    /// Statement dropping the source feature id column once it is no longer
+    /// needed, leaving the layout described at `create_table_sql`
+    pub fn drop_source_fid_sql(&self) -> String {
+        format!(
+            "ALTER TABLE {} DROP COLUMN {}",
+            self.qualified_name(),
+            quote_ident(SOURCE_FID_COLUMN)
+        )
+    }
+}
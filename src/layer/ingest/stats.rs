@@ -0,0 +1,47 @@
+use super::{GEOMETRY_COLUMN, TableSchema, quote_ident};
+use anyhow::Result;
+use sqlx::PgConnection;
+
+/// Spatial summary of a loaded table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableStats {
+    /// Extent in EPSG:4326 as [min x, min y, max x, max y]
+    pub bbox: Option<Vec<f64>>,
+    /// PostGIS geometry type name, `GEOMETRY` when types are mixed
+    pub geometry_type: Option<String>,
+}
+
+/// Compute the extent and geometry type of a freshly loaded table.
+pub async fn table_stats(conn: &mut PgConnection, schema: &TableSchema) -> Result<TableStats> {
+    let table = schema.qualified_name();
+    let geom = quote_ident(GEOMETRY_COLUMN);
+
+    // The extent is kept in longitude and latitude so it can be compared
+    // with tile bounds whatever the storage SRID
+    let extent: Option<(f64, f64, f64, f64)> = sqlx::query_as(&format!(
+        "SELECT ST_XMin(extent), ST_YMin(extent), ST_XMax(extent), ST_YMax(extent) \
+         FROM (SELECT ST_Extent(ST_Transform({}, 4326)) AS extent FROM {}) AS e \
+         WHERE extent IS NOT NULL",
+        geom, table
+    ))
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let geometry_types: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT DISTINCT GeometryType({}) FROM {} WHERE {} IS NOT NULL",
+        geom, table, geom
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let geometry_type = match geometry_types.as_slice() {
+        [] => None,
+        [geometry_type] => Some(geometry_type.clone()),
+        _ => Some("GEOMETRY".to_string()),
+    };
+
+    Ok(TableStats {
+        bbox: extent.map(|(min_x, min_y, max_x, max_y)| vec![min_x, min_y, max_x, max_y]),
+        geometry_type,
+    })
+}
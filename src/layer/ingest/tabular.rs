@@ -0,0 +1,128 @@
+use super::{IngestOptions, InvalidUpload};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Column names recognised as x coordinates, in order of preference
+const X_FIELD_NAMES: &[&str] = &[
+    "longitude",
+    "lon",
+    "lng",
+    "long",
+    "x",
+    "easting",
+    "east",
+    "x_coord",
+    "xcoord",
+];
+
+/// Column names recognised as y coordinates, in order of preference
+const Y_FIELD_NAMES: &[&str] = &[
+    "latitude", "lat", "y", "northing", "north", "y_coord", "ycoord",
+];
+
+/// Column names recognised as WKT geometries, in order of preference
+const WKT_FIELD_NAMES: &[&str] = &["wkt", "wkt_geom", "geometry", "geom", "the_geom", "shape"];
+
+/// Coordinate column names that imply longitude and latitude
+const LONGITUDE_NAMES: &[&str] = &["longitude", "lon", "lng", "long"];
+const LATITUDE_NAMES: &[&str] = &["latitude", "lat"];
+
+/// Where the geometry of each feature comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum GeometrySource {
+    /// Geometry read by the GDAL driver
+    Layer,
+    /// Point built from a pair of coordinate fields
+    Point { x_field: String, y_field: String },
+    /// Geometry parsed from a WKT text field
+    Wkt { field: String },
+}
+
+impl GeometrySource {
+    /// SRID implied by the source, EPSG:4326 for longitude and latitude columns
+    pub fn implied_srid(&self) -> Option<i32> {
+        match self {
+            GeometrySource::Point { x_field, y_field }
+                if LONGITUDE_NAMES.contains(&x_field.to_lowercase().as_str())
+                    && LATITUDE_NAMES.contains(&y_field.to_lowercase().as_str()) =>
+            {
+                Some(4326)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Pick the geometry source of a layer. Fields named in the upload metadata
+/// win; layers without a geometry of their own (CSV, XLSX) fall back to
+/// well known column names.
+pub fn detect_geometry_source(
+    field_names: &[String],
+    has_geometry: bool,
+    options: &IngestOptions,
+) -> Result<GeometrySource> {
+    if let Some(wkt_field) = &options.wkt_field {
+        return Ok(GeometrySource::Wkt {
+            field: find_field(field_names, "wkt_field", wkt_field)?,
+        });
+    }
+    if let (Some(x_field), Some(y_field)) = (&options.x_field, &options.y_field) {
+        return Ok(GeometrySource::Point {
+            x_field: find_field(field_names, "x_field", x_field)?,
+            y_field: find_field(field_names, "y_field", y_field)?,
+        });
+    }
+
+    if has_geometry {
+        return Ok(GeometrySource::Layer);
+    }
+
+    if let Some(field) = detect_field(field_names, WKT_FIELD_NAMES) {
+        return Ok(GeometrySource::Wkt { field });
+    }
+    if let (Some(x_field), Some(y_field)) = (
+        detect_field(field_names, X_FIELD_NAMES),
+        detect_field(field_names, Y_FIELD_NAMES),
+    ) {
+        return Ok(GeometrySource::Point { x_field, y_field });
+    }
+
+    Ok(GeometrySource::Layer)
+}
+
+/// Source field matching a name from the metadata, ignoring case.
+fn find_field(field_names: &[String], key: &str, name: &str) -> Result<String> {
+    field_names
+        .iter()
+        .find(|field_name| field_name.eq_ignore_ascii_case(name))
+        .cloned()
+        .ok_or_else(|| {
+            InvalidUpload(format!(
+                "{} '{}' not found, available fields: {}",
+                key,
+                name,
+                field_names.join(", ")
+            ))
+            .into()
+        })
+}
+
+/// First source field matching one of the candidate names, ignoring case.
+fn detect_field(field_names: &[String], candidates: &[&str]) -> Option<String> {
+    candidates.iter().find_map(|candidate| {
+        field_names
+            .iter()
+            .find(|field_name| field_name.trim().eq_ignore_ascii_case(candidate))
+            .cloned()
+    })
+}
+
+/// Parse a coordinate, accepting a decimal comma as some spreadsheets use.
+pub fn parse_coordinate(text: &str) -> Option<f64> {
+    let text = text.trim();
+    text.parse::<f64>()
+        .or_else(|_| text.replace(',', ".").parse())
+        .ok()
+        .filter(|value| value.is_finite())
+}
@@ -0,0 +1,125 @@
+use super::{ColumnSchema, GEOMETRY_COLUMN, ID_COLUMN, postgis_connector, quote_ident};
+use crate::config::AppState;
+use crate::layer::{Layer, LayerVersion};
+use anyhow::{Result, anyhow};
+use gridwalk_core::VectorConnector;
+use uuid::Uuid;
+
+/// A table of layer data to render tiles from
+struct TileSource<'a> {
+    layer_id: Uuid,
+    table_name: &'a str,
+    /// EPSG code of the stored geometries
+    srid: i32,
+    fields: &'a [ColumnSchema],
+}
+
+/// Render a Mapbox Vector Tile from the data a layer serves, its table or
+/// the view over its active version. The tile layer is named after the
+/// layer id and carries every field.
+pub async fn layer_tile(
+    state: &AppState,
+    layer: &Layer,
+    z: u32,
+    x: u32,
+    y: u32,
+) -> Result<Vec<u8>> {
+    let table_name = layer
+        .table_name
+        .as_deref()
+        .ok_or_else(|| anyhow!("Layer {} has no table", layer.id))?;
+    let srid = layer
+        .srid
+        .ok_or_else(|| anyhow!("Layer {} has no SRID", layer.id))?;
+
+    let source = TileSource {
+        layer_id: layer.id,
+        table_name,
+        srid,
+        fields: layer.fields.as_deref().unwrap_or_default(),
+    };
+    render_tile(state, &source, z, x, y).await
+}
+
+/// Render a tile through the PostGIS connector, for layers loaded before
+/// their table name and SRID were recorded on the layer.
+pub async fn legacy_tile(
+    state: &AppState,
+    layer_id: Uuid,
+    z: u32,
+    x: u32,
+    y: u32,
+) -> Result<Vec<u8>> {
+    postgis_connector(state)?
+        .get_tile(&layer_id, z, x, y)
+        .await
+        .map_err(|e| anyhow!("{}", e))
+}
+
+/// Render a Mapbox Vector Tile from one version of a layer. The tile layer
+/// is named after the layer id and carries every field of the version.
+pub async fn version_tile(
+    state: &AppState,
+    version: &LayerVersion,
+    z: u32,
+    x: u32,
+    y: u32,
+) -> Result<Vec<u8>> {
+    let srid = version
+        .srid
+        .ok_or_else(|| anyhow!("Version {} has no SRID", version.version))?;
+
+    let source = TileSource {
+        layer_id: version.layer_id,
+        table_name: &version.table_name,
+        srid,
+        fields: version.fields.as_deref().unwrap_or_default(),
+    };
+    render_tile(state, &source, z, x, y).await
+}
+
+/// Render a tile from a table with the layout ingests create, see
+/// `TableSchema::create_table_sql`.
+async fn render_tile(
+    state: &AppState,
+    source: &TileSource<'_>,
+    z: u32,
+    x: u32,
+    y: u32,
+) -> Result<Vec<u8>> {
+    let postgis_connector = postgis_connector(state)?;
+
+    let geom = quote_ident(GEOMETRY_COLUMN);
+    let mut columns = vec![format!("t.{}", quote_ident(ID_COLUMN))];
+    columns.extend(
+        source
+            .fields
+            .iter()
+            .map(|field| format!("t.{}", quote_ident(&field.name))),
+    );
+
+    let query = format!(
+        "WITH bounds AS (SELECT ST_TileEnvelope($1, $2, $3) AS envelope), \
+         features AS ( \
+             SELECT ST_AsMVTGeom(ST_Transform(t.{geom}, 3857), bounds.envelope, 4096, 64, true) AS {geom}, {columns} \
+             FROM {schema}.{table} AS t, bounds \
+             WHERE t.{geom} && ST_Transform(bounds.envelope, {srid}) \
+         ) \
+         SELECT ST_AsMVT(features.*, $4, 4096, '{geom_name}') FROM features WHERE {geom} IS NOT NULL",
+        geom = geom,
+        columns = columns.join(", "),
+        schema = quote_ident(&state.layer_schema),
+        table = quote_ident(source.table_name),
+        srid = source.srid,
+        geom_name = GEOMETRY_COLUMN,
+    );
+
+    let tile: Option<Vec<u8>> = sqlx::query_scalar(&query)
+        .bind(z as i32)
+        .bind(x as i32)
+        .bind(y as i32)
+        .bind(source.layer_id.to_string())
+        .fetch_one(&postgis_connector.pool)
+        .await?;
+    Ok(tile.unwrap_or_default())
+}
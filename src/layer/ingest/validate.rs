@@ -0,0 +1,114 @@
+use super::{
+    FeatureIssue, GEOMETRY_COLUMN, ID_COLUMN, InvalidUpload, SOURCE_FID_COLUMN, TableSchema,
+    quote_ident,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use strum_macros::{Display, EnumString};
+
+/// Number of invalid features kept as samples in the report
+const MAX_SAMPLES: i64 = 20;
+
+/// What to do with features whose geometry is invalid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Display, EnumString, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum GeometryPolicy {
+    /// Fail the whole ingest
+    Reject,
+    /// Fix the geometry with `ST_MakeValid`
+    #[default]
+    Repair,
+    /// Leave the feature out
+    Skip,
+}
+
+/// Result of checking the geometries of a loaded table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeometryValidation {
+    pub policy: GeometryPolicy,
+    pub invalid: u64,
+    pub repaired: u64,
+    pub skipped: u64,
+    /// Sample of the invalid features, by id in the source file, and why
+    #[serde(default)]
+    pub samples: Vec<FeatureIssue>,
+}
+
+/// Find invalid geometries in a freshly loaded table and apply the policy.
+/// Runs on the loading transaction, so a rejected layer leaves nothing behind.
+/// Samples are reported by source feature id, which the table still holds.
+pub async fn validate_geometries(
+    conn: &mut PgConnection,
+    schema: &TableSchema,
+    policy: GeometryPolicy,
+) -> Result<GeometryValidation> {
+    let table = schema.qualified_name();
+    let id = quote_ident(ID_COLUMN);
+    let source_fid = quote_ident(SOURCE_FID_COLUMN);
+    let geom = quote_ident(GEOMETRY_COLUMN);
+    let invalid_filter = format!("{} IS NOT NULL AND NOT ST_IsValid({})", geom, geom);
+
+    let invalid: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM {} WHERE {}",
+        table, invalid_filter
+    ))
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let mut validation = GeometryValidation {
+        policy,
+        invalid: invalid as u64,
+        ..Default::default()
+    };
+    if invalid == 0 {
+        return Ok(validation);
+    }
+
+    let samples: Vec<(i64, String)> = sqlx::query_as(&format!(
+        "SELECT {}, ST_IsValidReason({}) FROM {} WHERE {} ORDER BY {} LIMIT {}",
+        source_fid, geom, table, invalid_filter, id, MAX_SAMPLES
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+    validation.samples = samples
+        .into_iter()
+        .map(|(feature_id, reason)| FeatureIssue {
+            feature_id: feature_id as u64,
+            reason,
+        })
+        .collect();
+
+    match policy {
+        GeometryPolicy::Reject => {
+            let example = validation
+                .samples
+                .first()
+                .map(|sample| format!(", e.g. feature {}: {}", sample.feature_id, sample.reason))
+                .unwrap_or_default();
+            return Err(InvalidUpload(format!(
+                "Layer '{}' has {} invalid geometries{}",
+                schema.source_layer, invalid, example
+            ))
+            .into());
+        }
+        GeometryPolicy::Repair => {
+            let result = sqlx::query(&format!(
+                "UPDATE {} SET {} = ST_MakeValid({}) WHERE {}",
+                table, geom, geom, invalid_filter
+            ))
+            .execute(&mut *conn)
+            .await?;
+            validation.repaired = result.rows_affected();
+        }
+        GeometryPolicy::Skip => {
+            let result = sqlx::query(&format!("DELETE FROM {} WHERE {}", table, invalid_filter))
+                .execute(&mut *conn)
+                .await?;
+            validation.skipped = result.rows_affected();
+        }
+    }
+
+    Ok(validation)
+}
@@ -0,0 +1,100 @@
+use super::{GEOMETRY_COLUMN, ID_COLUMN, postgis_connector, quote_ident};
+use crate::config::AppState;
+use crate::layer::LayerVersion;
+use anyhow::{Result, anyhow};
+use sqlx::PgConnection;
+
+/// Table holding one version of a layer
+pub fn version_table_name(table_name: &str, version: i32) -> String {
+    format!("{}_v{}", table_name, version)
+}
+
+/// Point a layer's table name at one of its version tables. The view is
+/// recreated rather than replaced as versions may have different columns.
+pub async fn point_view_at(
+    conn: &mut PgConnection,
+    data_schema: &str,
+    table_name: &str,
+    version_table: &str,
+) -> Result<()> {
+    let view = format!("{}.{}", quote_ident(data_schema), quote_ident(table_name));
+    sqlx::query(&format!("DROP VIEW IF EXISTS {}", view))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Failed to drop layer view: {}", e))?;
+    sqlx::query(&format!(
+        "CREATE VIEW {} AS SELECT * FROM {}.{}",
+        view,
+        quote_ident(data_schema),
+        quote_ident(version_table)
+    ))
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| anyhow!("Failed to create layer view: {}", e))?;
+    Ok(())
+}
+
+/// Make a stored version the data served under a layer's table name. The
+/// caller holds the lock on the layer's row and records the change once
+/// this has committed.
+pub async fn activate_version(
+    state: &AppState,
+    table_name: &str,
+    version: &LayerVersion,
+) -> Result<()> {
+    let mut tx = postgis_connector(state)?.pool.begin().await?;
+    point_view_at(
+        &mut tx,
+        &state.layer_schema,
+        table_name,
+        &version.table_name,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A page of the features of one version of a layer as a GeoJSON feature
+/// collection in EPSG:4326, ordered by id.
+pub async fn version_features(
+    state: &AppState,
+    version: &LayerVersion,
+    limit: u64,
+    offset: u64,
+) -> Result<serde_json::Value> {
+    let postgis_connector = postgis_connector(state)?;
+
+    let geom = quote_ident(GEOMETRY_COLUMN);
+    let id = quote_ident(ID_COLUMN);
+    let mut columns = vec![
+        format!("ST_Transform({}, 4326) AS {}", geom, geom),
+        id.clone(),
+    ];
+    columns.extend(
+        version
+            .fields
+            .iter()
+            .flatten()
+            .map(|field| quote_ident(&field.name)),
+    );
+
+    let query = format!(
+        "SELECT jsonb_build_object('type', 'FeatureCollection', 'features', \
+             COALESCE(jsonb_agg(ST_AsGeoJSON(page.*, '{geom_name}')::jsonb ORDER BY page.{id}), '[]'::jsonb)) \
+         FROM (SELECT {columns} FROM {schema}.{table} ORDER BY {id} LIMIT $1 OFFSET $2) AS page",
+        geom_name = GEOMETRY_COLUMN,
+        id = id,
+        columns = columns.join(", "),
+        schema = quote_ident(&state.layer_schema),
+        table = quote_ident(&version.table_name),
+    );
+
+    let features: serde_json::Value =
+        sqlx::query_scalar::<_, sqlx::types::Json<serde_json::Value>>(&query)
+            .bind(limit as i64)
+            .bind(offset as i64)
+            .fetch_one(&postgis_connector.pool)
+            .await?
+            .0;
+    Ok(features)
+}
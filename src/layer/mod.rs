@@ -1,5 +1,10 @@
 mod core;
 mod endpoints;
+pub mod ingest;
+pub mod tus;
+pub mod upload;
+mod version;
 
-use core::*;
+pub use core::*;
 pub use endpoints::*;
+pub use version::*;
@@ -0,0 +1,439 @@
+use crate::layer::ingest::IngestOptions;
+use axum::http::{HeaderMap, StatusCode, header::HeaderValue};
+use base64::prelude::*;
+use serde_json::json;
+use sha2::Digest;
+use uuid::Uuid;
+
+/// TUS protocol version implemented by the server
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// TUS extensions implemented by the upload handlers
+pub const TUS_EXTENSIONS: &[&str] = &[
+    "creation",
+    "creation-defer-length",
+    "creation-with-upload",
+    "termination",
+    "checksum",
+    "expiration",
+    "concatenation",
+];
+
+/// Checksum algorithms accepted in `Upload-Checksum`
+pub const TUS_CHECKSUM_ALGORITHMS: &[&str] = &["sha1", "sha256", "md5"];
+
+/// Check the client's `Tus-Resumable` header against the supported version.
+pub fn check_tus_resumable(
+    headers: &HeaderMap,
+) -> Result<(), (StatusCode, axum::Json<serde_json::Value>)> {
+    let tus_version = headers
+        .get("tus-resumable")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                axum::Json(json!({"error": "Missing Tus-Resumable header"})),
+            )
+        })?;
+
+    if tus_version != TUS_VERSION {
+        return Err((
+            StatusCode::PRECONDITION_FAILED,
+            axum::Json(json!({
+                "error": "Unsupported Tus-Resumable version",
+                "supported": [TUS_VERSION],
+                "received": tus_version
+            })),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Format an upload expiry as an RFC 7231 date for the `Upload-Expires` header.
+pub fn upload_expires_header(expires_at: chrono::DateTime<chrono::Utc>) -> HeaderValue {
+    HeaderValue::from_str(&expires_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+        .expect("formatted date is a valid header value")
+}
+
+/// Incremental hasher for one of the supported checksum algorithms
+pub enum ChecksumHasher {
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Md5(md5::Md5),
+}
+
+impl ChecksumHasher {
+    pub fn new(algorithm: &str) -> Option<Self> {
+        match algorithm {
+            "sha1" => Some(ChecksumHasher::Sha1(sha1::Sha1::new())),
+            "sha256" => Some(ChecksumHasher::Sha256(sha2::Sha256::new())),
+            "md5" => Some(ChecksumHasher::Md5(md5::Md5::new())),
+            _ => None,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumHasher::Sha1(hasher) => hasher.update(data),
+            ChecksumHasher::Sha256(hasher) => hasher.update(data),
+            ChecksumHasher::Md5(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            ChecksumHasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            ChecksumHasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            ChecksumHasher::Md5(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Checksum sent by the client for a single PATCH chunk
+pub struct UploadChecksum {
+    pub algorithm: String,
+    pub expected: Vec<u8>,
+}
+
+impl UploadChecksum {
+    pub fn hasher(&self) -> ChecksumHasher {
+        // The algorithm was validated when the header was parsed
+        ChecksumHasher::new(&self.algorithm).expect("supported checksum algorithm")
+    }
+
+    pub fn matches(&self, digest: &[u8]) -> bool {
+        self.expected == digest
+    }
+}
+
+/// Parse the optional `Upload-Checksum` header (`<algorithm> <base64 digest>`).
+pub fn parse_upload_checksum(
+    headers: &HeaderMap,
+) -> Result<Option<UploadChecksum>, (StatusCode, axum::Json<serde_json::Value>)> {
+    let Some(checksum_header) = headers.get("upload-checksum") else {
+        return Ok(None);
+    };
+
+    let invalid_checksum = || {
+        (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": "Invalid Upload-Checksum header"})),
+        )
+    };
+
+    let checksum_str = checksum_header.to_str().map_err(|_| invalid_checksum())?;
+    let (algorithm, encoded_digest) = checksum_str
+        .trim()
+        .split_once(' ')
+        .ok_or_else(invalid_checksum)?;
+
+    if !TUS_CHECKSUM_ALGORITHMS.contains(&algorithm) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({
+                "error": "Unsupported checksum algorithm",
+                "supported": TUS_CHECKSUM_ALGORITHMS
+            })),
+        ));
+    }
+
+    let expected = BASE64_STANDARD
+        .decode(encoded_digest.trim())
+        .map_err(|_| invalid_checksum())?;
+
+    Ok(Some(UploadChecksum {
+        algorithm: algorithm.to_string(),
+        expected,
+    }))
+}
+
+/// Error returned when a chunk does not match its `Upload-Checksum`
+pub fn checksum_mismatch() -> (StatusCode, axum::Json<serde_json::Value>) {
+    (
+        StatusCode::from_u16(460).expect("460 is a valid status code"),
+        axum::Json(json!({"error": "Checksum Mismatch"})),
+    )
+}
+
+/// Parsed `Upload-Concat` header
+pub enum UploadConcat {
+    /// A partial upload that will later be joined into a final upload
+    Partial,
+    /// A final upload made of the listed partial uploads, in order
+    Final(Vec<Uuid>),
+}
+
+/// Parse the optional `Upload-Concat` header. Final uploads list their
+/// partial uploads as space separated URLs ending in the layer id.
+pub fn parse_upload_concat(
+    headers: &HeaderMap,
+) -> Result<Option<UploadConcat>, (StatusCode, axum::Json<serde_json::Value>)> {
+    let Some(concat_header) = headers.get("upload-concat") else {
+        return Ok(None);
+    };
+
+    let invalid_concat = || {
+        (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": "Invalid Upload-Concat header"})),
+        )
+    };
+
+    let concat_str = concat_header.to_str().map_err(|_| invalid_concat())?.trim();
+    if concat_str == "partial" {
+        return Ok(Some(UploadConcat::Partial));
+    }
+
+    let partial_urls = concat_str
+        .strip_prefix("final;")
+        .ok_or_else(invalid_concat)?;
+
+    let partial_ids = partial_urls
+        .split_whitespace()
+        .map(|url| {
+            url.trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .and_then(|id| Uuid::parse_str(id).ok())
+                .ok_or_else(invalid_concat)
+        })
+        .collect::<Result<Vec<Uuid>, _>>()?;
+
+    if partial_ids.is_empty() {
+        return Err(invalid_concat());
+    }
+
+    // Each partial upload is consumed once
+    for (position, partial_id) in partial_ids.iter().enumerate() {
+        if partial_ids[..position].contains(partial_id) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                axum::Json(json!({
+                    "error": format!("Partial upload {} is listed more than once", partial_id)
+                })),
+            ));
+        }
+    }
+
+    Ok(Some(UploadConcat::Final(partial_ids)))
+}
+
+/// Upload description and ingest options sent in `Upload-Metadata`
+#[derive(Debug, Default)]
+pub struct UploadMetadata {
+    pub name: Option<String>,
+    pub upload_type: Option<String>,
+    pub ingest_options: IngestOptions,
+}
+
+/// Parse the optional `Upload-Metadata` header: comma separated keys, each
+/// with a base64 encoded value. `name` and `upload_type` describe the upload,
+/// every other key configures the ingest. Keys sent without a value are
+/// ignored.
+pub fn parse_upload_metadata(
+    headers: &HeaderMap,
+) -> Result<Option<UploadMetadata>, (StatusCode, axum::Json<serde_json::Value>)> {
+    let Some(metadata_header) = headers.get("upload-metadata") else {
+        return Ok(None);
+    };
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({"error": message})),
+        )
+    };
+
+    let metadata_str = metadata_header
+        .to_str()
+        .map_err(|_| bad_request("Invalid Upload-Metadata header".to_string()))?;
+
+    let mut metadata = UploadMetadata::default();
+    for pair in metadata_str.split(',').map(str::trim) {
+        let Some((key, encoded_value)) = pair.split_once(' ') else {
+            continue;
+        };
+        let value = BASE64_STANDARD
+            .decode(encoded_value.trim())
+            .ok()
+            .and_then(|decoded_value| String::from_utf8(decoded_value).ok())
+            .ok_or_else(|| bad_request(format!("Invalid Upload-Metadata value for '{}'", key)))?;
+
+        match key {
+            "name" => {
+                let trimmed_name = value.trim().to_string();
+                if !trimmed_name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-')
+                {
+                    return Err(bad_request(
+                        "Name can only contain alphanumeric characters, spaces, hyphens, and underscores"
+                            .to_string(),
+                    ));
+                }
+                metadata.name = Some(trimmed_name);
+            }
+            "upload_type" => {
+                let trimmed_type = value.trim().to_string();
+                if !trimmed_type.chars().all(|c| c.is_alphabetic()) {
+                    return Err(bad_request(
+                        "Upload type can only contain letters".to_string(),
+                    ));
+                }
+                metadata.upload_type = Some(trimmed_type);
+            }
+            // Everything else configures the ingest
+            key => metadata
+                .ingest_options
+                .set(key, &value)
+                .map_err(bad_request)?,
+        }
+    }
+
+    metadata.ingest_options.validate().map_err(bad_request)?;
+    Ok(Some(metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn rejection<T>(
+        result: Result<T, (StatusCode, axum::Json<serde_json::Value>)>,
+    ) -> Option<StatusCode> {
+        result.err().map(|(status, _)| status)
+    }
+
+    #[test]
+    fn checksum_is_optional() {
+        assert!(matches!(parse_upload_checksum(&HeaderMap::new()), Ok(None)));
+    }
+
+    #[test]
+    fn checksum_decodes_the_digest() {
+        let checksum = parse_upload_checksum(&headers("upload-checksum", "sha1 AAECAw=="))
+            .ok()
+            .flatten()
+            .expect("valid checksum");
+        assert_eq!(checksum.algorithm, "sha1");
+        assert!(checksum.matches(&[0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn checksum_rejects_malformed_headers() {
+        for value in ["sha1", "sha1 not-base64!", "", "sha1AAECAw=="] {
+            assert_eq!(
+                rejection(parse_upload_checksum(&headers("upload-checksum", value))),
+                Some(StatusCode::BAD_REQUEST),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_rejects_unsupported_algorithms() {
+        assert_eq!(
+            rejection(parse_upload_checksum(&headers(
+                "upload-checksum",
+                "crc32 AAECAw=="
+            ))),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn concat_parses_partial_uploads() {
+        assert!(matches!(
+            parse_upload_concat(&headers("upload-concat", "partial")),
+            Ok(Some(UploadConcat::Partial))
+        ));
+    }
+
+    #[test]
+    fn concat_parses_final_uploads_in_order() {
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let value = format!(
+            "final;/layers/{} https://example.com/layers/{}/",
+            first, second
+        );
+        let Ok(Some(UploadConcat::Final(ids))) =
+            parse_upload_concat(&headers("upload-concat", &value))
+        else {
+            panic!("expected a final upload");
+        };
+        assert_eq!(ids, vec![first, second]);
+    }
+
+    #[test]
+    fn concat_rejects_malformed_headers() {
+        for value in [
+            "final;",
+            "final; ",
+            "final;/layers/not-a-uuid",
+            "final /layers/0b7c6a4e-7f3c-4d3b-9a0e-2d1f5c9b8a70",
+            "parallel",
+        ] {
+            assert_eq!(
+                rejection(parse_upload_concat(&headers("upload-concat", value))),
+                Some(StatusCode::BAD_REQUEST),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn concat_rejects_duplicate_partial_uploads() {
+        let id = Uuid::new_v4();
+        let value = format!("final;/layers/{} /layers/{}", id, id);
+        assert_eq!(
+            rejection(parse_upload_concat(&headers("upload-concat", &value))),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn metadata_decodes_upload_description() {
+        // name "roads 2024", upload_type "gpkg", a key without a value
+        let metadata = parse_upload_metadata(&headers(
+            "upload-metadata",
+            "name cm9hZHMgMjAyNA==, upload_type Z3BrZw==, is_confidential",
+        ))
+        .ok()
+        .flatten()
+        .unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("roads 2024"));
+        assert_eq!(metadata.upload_type.as_deref(), Some("gpkg"));
+    }
+
+    #[test]
+    fn metadata_rejects_invalid_values() {
+        for value in [
+            "name not-base64!",
+            "upload_type //79",
+            "name cm9hZHM7",
+            "upload_type Z3BrZzE=",
+        ] {
+            assert_eq!(
+                rejection(parse_upload_metadata(&headers("upload-metadata", value))),
+                Some(StatusCode::BAD_REQUEST),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn metadata_is_optional() {
+        assert!(matches!(parse_upload_metadata(&HeaderMap::new()), Ok(None)));
+    }
+}
@@ -0,0 +1,472 @@
+use crate::config::AppState;
+use crate::jobs::IngestJob;
+use crate::layer::tus::{UploadChecksum, checksum_mismatch};
+use crate::layer::{Layer, LayerStatus};
+use axum::{body::Body, http::StatusCode};
+use futures::StreamExt;
+use gridwalk_core::LayerCore;
+use serde_json::json;
+use sqlx::{Connection, PgConnection};
+use std::io::SeekFrom;
+use tokio::{
+    fs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Why a request body stopped streaming before reaching its end
+enum StreamStop {
+    TooLarge(&'static str),
+    Interrupted(String),
+}
+
+/// Stream a request body to the layer's upload file at its current offset and
+/// persist the new offset. Bytes already written are kept when the client
+/// disconnects, unless an `Upload-Checksum` was sent and cannot be verified.
+/// Only one request writes to an upload at a time, others get a 409.
+/// Once the upload is complete the layer moves to `Processing` and an ingest
+/// job is queued in the same transaction. Returns whether the upload is complete.
+pub async fn append_body(
+    state: &AppState,
+    layer: &mut Layer,
+    body: Body,
+    upload_checksum: Option<&UploadChecksum>,
+) -> Result<bool, (StatusCode, axum::Json<serde_json::Value>)> {
+    // Deferred uploads are still bound by the server maximum
+    let max_offset = layer
+        .total_size
+        .unwrap_or(state.max_upload_size)
+        .min(state.max_upload_size);
+
+    // Writers are serialized by a session lock on a connection of its own, no
+    // pooled connection or transaction is held while the body streams
+    let mut lock_conn = PgConnection::connect_with(&state.app_db.connect_options())
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to connect to database: {}", e)})),
+            )
+        })?;
+    let locked = Layer::try_lock_upload(layer.id, &mut lock_conn)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to lock upload: {}", e)})),
+            )
+        })?;
+    if !locked {
+        let _ = lock_conn.close().await;
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Another request is writing to this upload"})),
+        ));
+    }
+
+    let result = write_body(state, layer, body, upload_checksum, max_offset).await;
+
+    // Ending the session releases the lock
+    if let Err(e) = lock_conn.close().await {
+        warn!(
+            "Failed to release the upload lock of layer {}: {}",
+            layer.id, e
+        );
+    }
+    result
+}
+
+/// Append a request body to the upload file while the writer lock is held.
+async fn write_body(
+    state: &AppState,
+    layer: &mut Layer,
+    body: Body,
+    upload_checksum: Option<&UploadChecksum>,
+    max_offset: i64,
+) -> Result<bool, (StatusCode, axum::Json<serde_json::Value>)> {
+    // A request that held the lock before may have moved the offset on since
+    // the layer was read, the file must not be cut back to a stale offset
+    let current = Layer::get(layer.id, &*state.app_db).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Database error: {}", e)})),
+        )
+    })?;
+    if current.status != LayerStatus::Uploading || current.current_offset != layer.current_offset {
+        return Err(upload_changed(state, layer.id).await);
+    }
+
+    // Open the upload file and drop anything past the recorded offset, which
+    // can only be left over from a request that failed half way
+    let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(&upload_file_path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to open upload file: {}", e)})),
+            )
+        })?;
+
+    let start_offset = layer.current_offset;
+    file.set_len(start_offset as u64).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to prepare upload file: {}", e)})),
+        )
+    })?;
+    file.seek(SeekFrom::Start(start_offset as u64))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to prepare upload file: {}", e)})),
+            )
+        })?;
+
+    let mut hasher = upload_checksum.map(|checksum| checksum.hasher());
+    let mut written: i64 = 0;
+    let mut stop: Option<StreamStop> = None;
+
+    // Write the data as it arrives, only one frame is held in memory
+    let mut stream = body.into_data_stream();
+    while let Some(frame) = stream.next().await {
+        let data = match frame {
+            Ok(data) => data,
+            Err(e) => {
+                stop = Some(StreamStop::Interrupted(e.to_string()));
+                break;
+            }
+        };
+
+        let frame_len = data.len() as i64;
+        if written + frame_len > state.max_chunk_size {
+            stop = Some(StreamStop::TooLarge(
+                "Request body exceeds the maximum chunk size",
+            ));
+            break;
+        }
+        if start_offset + written + frame_len > max_offset {
+            stop = Some(StreamStop::TooLarge(if layer.total_size.is_some() {
+                "Upload would exceed declared file size"
+            } else {
+                "Upload would exceed the maximum upload size"
+            }));
+            break;
+        }
+
+        if let Err(e) = file.write_all(&data).await {
+            stop = Some(StreamStop::Interrupted(format!(
+                "Failed to write to upload file: {}",
+                e
+            )));
+            break;
+        }
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&data);
+        }
+        written += frame_len;
+    }
+
+    file.flush().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to flush upload file: {}", e)})),
+        )
+    })?;
+
+    // A chunk with a checksum is all or nothing: discard it unless it arrived
+    // in full and matches
+    if let (Some(upload_checksum), Some(hasher)) = (upload_checksum, hasher) {
+        let verified = stop.is_none() && upload_checksum.matches(&hasher.finalize());
+        if !verified {
+            file.set_len(start_offset as u64).await.map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Failed to discard chunk: {}", e)})),
+                )
+            })?;
+            return Err(match stop {
+                Some(StreamStop::TooLarge(message)) => (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    axum::Json(json!({"error": message})),
+                ),
+                Some(StreamStop::Interrupted(message)) => (
+                    StatusCode::BAD_REQUEST,
+                    axum::Json(json!({"error": format!("Upload interrupted: {}", message)})),
+                ),
+                None => checksum_mismatch(),
+            });
+        }
+    }
+
+    // Update layer's current offset and updated_at timestamp
+    layer.current_offset += written;
+    layer.updated_at = chrono::Utc::now();
+
+    // Every successful chunk extends the expiry window
+    layer.expires_at = Some(layer.updated_at + chrono::Duration::seconds(state.upload_expiry_secs));
+
+    // Once the last byte has landed, hand the file over to the ingest workers
+    let upload_complete = stop.is_none()
+        && layer
+            .total_size
+            .is_some_and(|total_size| layer.current_offset >= total_size);
+
+    // Partial uploads wait to be concatenated instead of being ingested
+    let queue_ingest = upload_complete && !layer.is_partial();
+
+    if queue_ingest {
+        layer.status = LayerStatus::Processing;
+    }
+
+    // Only the columns a PATCH owns are written, and only if the upload was
+    // not cancelled in the meantime. The job is queued in the same transaction.
+    let mut tx = state.app_db.begin().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to start transaction: {}", e)})),
+        )
+    })?;
+    let recorded = layer
+        .record_chunk(start_offset, &mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to update layer: {}", e)})),
+            )
+        })?;
+    if !recorded {
+        let _ = tx.rollback().await;
+        return Err(upload_changed(state, layer.id).await);
+    }
+
+    if queue_ingest {
+        let job = IngestJob::new(layer.id, state.ingest_config.max_attempts);
+        job.save(&mut *tx).await.map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to queue ingest job: {}", e)})),
+            )
+        })?;
+        info!(
+            "Upload complete for layer {}, queued ingest job {}",
+            layer.id, job.id
+        );
+    }
+
+    tx.commit().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to commit transaction: {}", e)})),
+        )
+    })?;
+
+    // The bytes that did land are kept, report why the rest did not
+    match stop {
+        Some(StreamStop::TooLarge(message)) => Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            axum::Json(json!({"error": message, "offset": layer.current_offset})),
+        )),
+        Some(StreamStop::Interrupted(message)) => {
+            warn!(
+                "Upload to layer {} interrupted at offset {}: {}",
+                layer.id, layer.current_offset, message
+            );
+            Err((
+                StatusCode::BAD_REQUEST,
+                axum::Json(json!({
+                    "error": format!("Upload interrupted: {}", message),
+                    "offset": layer.current_offset
+                })),
+            ))
+        }
+        None => Ok(upload_complete),
+    }
+}
+
+/// Response for a PATCH whose upload changed while its body was streaming:
+/// 410 once the upload was cancelled, 409 otherwise.
+async fn upload_changed(
+    state: &AppState,
+    layer_id: Uuid,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    match Layer::get(layer_id, &*state.app_db).await {
+        Ok(layer) if layer.status == LayerStatus::Cancelled => (
+            StatusCode::GONE,
+            axum::Json(json!({"error": "Upload has been cancelled"})),
+        ),
+        Ok(layer) => (
+            StatusCode::CONFLICT,
+            axum::Json(json!({
+                "error": "Upload changed while the chunk was written, please retry",
+                "offset": layer.current_offset
+            })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Database error: {}", e)})),
+        ),
+    }
+}
+
+/// Join completed partial uploads into the final upload's file and queue it
+/// for ingest. The partial layers are removed once their data has been copied.
+pub async fn concatenate_partials(
+    state: &AppState,
+    layer: &mut Layer,
+    partial_ids: &[Uuid],
+) -> Result<(), (StatusCode, axum::Json<serde_json::Value>)> {
+    let mut partials = Vec::with_capacity(partial_ids.len());
+    for partial_id in partial_ids {
+        let partial = Layer::get(*partial_id, &*state.app_db)
+            .await
+            .map_err(|e| match e.downcast_ref::<sqlx::Error>() {
+                Some(sqlx::Error::RowNotFound) => (
+                    StatusCode::BAD_REQUEST,
+                    axum::Json(
+                        json!({"error": format!("Partial upload {} not found", partial_id)}),
+                    ),
+                ),
+                _ => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Database error: {}", e)})),
+                ),
+            })?;
+
+        let is_complete = partial
+            .total_size
+            .is_some_and(|total_size| partial.current_offset == total_size);
+        if !partial.is_partial() || partial.status != LayerStatus::Uploading || !is_complete {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                axum::Json(json!({
+                    "error": format!("Upload {} is not a complete partial upload", partial_id)
+                })),
+            ));
+        }
+        partials.push(partial);
+    }
+
+    let total_size: i64 = partials.iter().map(|partial| partial.current_offset).sum();
+    if total_size > state.max_upload_size {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            axum::Json(json!({
+                "error": "Concatenated upload exceeds the maximum upload size",
+                "max_size": state.max_upload_size
+            })),
+        ));
+    }
+
+    // Copy the partial files, in order, into the final upload file
+    let upload_file_path = state.temp_data_path.join(layer.id.to_string());
+    let mut file = fs::File::create(&upload_file_path).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to create upload file: {}", e)})),
+        )
+    })?;
+
+    for partial in &partials {
+        let partial_file_path = state.temp_data_path.join(partial.id.to_string());
+        let mut partial_file = fs::File::open(&partial_file_path).await.map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to open partial upload file: {}", e)})),
+            )
+        })?;
+        tokio::io::copy(&mut partial_file, &mut file)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(json!({"error": format!("Failed to concatenate uploads: {}", e)})),
+                )
+            })?;
+    }
+
+    file.flush().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to flush upload file: {}", e)})),
+        )
+    })?;
+
+    layer.total_size = Some(total_size);
+    layer.current_offset = total_size;
+    layer.status = LayerStatus::Processing;
+    layer.expires_at = None;
+    layer.updated_at = chrono::Utc::now();
+
+    // Save the final upload, queue its ingest and drop the partials together
+    let mut tx = state.app_db.begin().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to start transaction: {}", e)})),
+        )
+    })?;
+
+    layer.save(&mut *tx).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to save layer: {}", e)})),
+        )
+    })?;
+
+    let job = IngestJob::new(layer.id, state.ingest_config.max_attempts);
+    job.save(&mut *tx).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to queue ingest job: {}", e)})),
+        )
+    })?;
+
+    let removed = Layer::delete_partials(partial_ids, &mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(json!({"error": format!("Failed to remove partial uploads: {}", e)})),
+            )
+        })?;
+
+    // Another final upload consumed some of the partials in the meantime
+    if removed != partials.len() as u64 {
+        let _ = tx.rollback().await;
+        let _ = fs::remove_file(&upload_file_path).await;
+        return Err((
+            StatusCode::CONFLICT,
+            axum::Json(json!({"error": "Partial uploads were already concatenated"})),
+        ));
+    }
+
+    tx.commit().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(json!({"error": format!("Failed to commit transaction: {}", e)})),
+        )
+    })?;
+
+    for partial in &partials {
+        let partial_file_path = state.temp_data_path.join(partial.id.to_string());
+        if let Err(e) = fs::remove_file(&partial_file_path).await {
+            warn!(
+                "Failed to remove partial upload file {:?}: {}",
+                partial_file_path, e
+            );
+        }
+    }
+
+    info!(
+        "Concatenated {} partial uploads into layer {}, queued ingest job {}",
+        partials.len(),
+        layer.id,
+        job.id
+    );
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use crate::layer::ingest::{ColumnSchema, IngestMode};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::{FromRow, Row};
+use uuid::Uuid;
+
+/// A snapshot of a layer's data. Every ingest into an existing layer adds
+/// one, and older versions are kept so the layer can be rolled back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayerVersion {
+    pub layer_id: Uuid,
+    pub version: i32,
+    /// Table in the layer data schema holding this version
+    pub table_name: String,
+    /// Upload that created the version, `None` for the layer's original data
+    pub upload_id: Option<Uuid>,
+    pub mode: IngestMode,
+    pub srid: Option<i32>,
+    pub bbox: Option<Vec<f64>>,
+    pub geometry_type: Option<String>,
+    pub feature_count: i64,
+    pub fields: Option<Vec<ColumnSchema>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl<'r> FromRow<'r, PgRow> for LayerVersion {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(LayerVersion {
+            layer_id: row.try_get("layer_id")?,
+            version: row.try_get("version")?,
+            table_name: row.try_get("table_name")?,
+            upload_id: row.try_get("upload_id")?,
+            mode: {
+                let mode_str: String = row.try_get("mode")?;
+                mode_str.parse().map_err(|e| {
+                    sqlx::Error::Decode(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Invalid mode value: {} - {}", mode_str, e),
+                    )))
+                })?
+            },
+            srid: row.try_get("srid")?,
+            bbox: row.try_get("bbox")?,
+            geometry_type: row.try_get("geometry_type")?,
+            feature_count: row.try_get("feature_count")?,
+            fields: row
+                .try_get::<Option<Json<Vec<ColumnSchema>>>, _>("fields")?
+                .map(|fields| fields.0),
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+impl LayerVersion {
+    pub async fn save<'e, E>(&self, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "INSERT INTO gridwalk.layer_versions (layer_id, version, table_name, upload_id, mode, srid, bbox, geometry_type, feature_count, fields, created_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
+
+        sqlx::query(query)
+            .bind(self.layer_id)
+            .bind(self.version)
+            .bind(&self.table_name)
+            .bind(self.upload_id)
+            .bind(self.mode.to_string())
+            .bind(self.srid)
+            .bind(&self.bbox)
+            .bind(&self.geometry_type)
+            .bind(self.feature_count)
+            .bind(self.fields.as_ref().map(Json))
+            .bind(self.created_at)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Every version of a layer, newest first.
+    pub async fn list<'e, E>(layer_id: Uuid, executor: E) -> Result<Vec<Self>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT * FROM gridwalk.layer_versions WHERE layer_id = $1 \
+                     ORDER BY version DESC";
+
+        let versions = sqlx::query_as::<_, LayerVersion>(query)
+            .bind(layer_id)
+            .fetch_all(executor)
+            .await?;
+        Ok(versions)
+    }
+
+    pub async fn get<'e, E>(layer_id: Uuid, version: i32, executor: E) -> Result<Self>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT * FROM gridwalk.layer_versions WHERE layer_id = $1 AND version = $2";
+
+        let version = sqlx::query_as::<_, LayerVersion>(query)
+            .bind(layer_id)
+            .bind(version)
+            .fetch_one(executor)
+            .await?;
+        Ok(version)
+    }
+
+    /// Remove the records of every version of a layer, once its tables have
+    /// been dropped.
+    pub async fn delete_all<'e, E>(layer_id: Uuid, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "DELETE FROM gridwalk.layer_versions WHERE layer_id = $1";
+
+        sqlx::query(query).bind(layer_id).execute(executor).await?;
+        Ok(())
+    }
+
+    /// Highest version number of a layer, which is not necessarily the
+    /// active one after a rollback.
+    pub async fn latest_number<'e, E>(layer_id: Uuid, executor: E) -> Result<Option<i32>>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let query = "SELECT MAX(version) FROM gridwalk.layer_versions WHERE layer_id = $1";
+
+        let latest = sqlx::query_scalar::<_, Option<i32>>(query)
+            .bind(layer_id)
+            .fetch_one(executor)
+            .await?;
+        Ok(latest)
+    }
+}
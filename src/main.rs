@@ -1,10 +1,11 @@
 mod config;
+mod jobs;
 mod layer;
 
 use anyhow::Result;
 use axum::{
     Router,
-    routing::{get, patch, post},
+    routing::{get, patch, post, put},
 };
 use tower_http::cors::CorsLayer;
 use tracing::info;
@@ -15,7 +16,7 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt().with_ansi(false).init();
 
     let config = config::Config::from_env()?;
-    let app_state = config::AppState::new(config).await?;
+    let app_state = std::sync::Arc::new(config::AppState::new(config).await?);
 
     sqlx::migrate!("./migrations")
         .run(&*app_state.app_db)
@@ -30,12 +31,42 @@ async fn main() -> Result<()> {
         println!("- {}", source);
     }
 
+    // Start background workers for layer ingestion and upload cleanup
+    jobs::spawn_workers(app_state.clone());
+    jobs::spawn_janitor(app_state.clone());
+
     let router = Router::new()
-        .route("/layers", post(layer::post_tus))
-        .route("/layers/:layer_id", patch(layer::patch_tus))
+        .route("/layers", post(layer::post_tus).options(layer::options_tus))
+        .route(
+            "/layers/:layer_id",
+            get(layer::get_layer)
+                .patch(layer::patch_tus)
+                .head(layer::head_tus)
+                .delete(layer::delete_tus)
+                .options(layer::options_tus),
+        )
         .route("/layers", get(layer::get_layers))
+        .route("/layers/validate", post(layer::validate_upload))
+        .route("/layers/:layer_id/retry", post(layer::retry_layer))
+        .route(
+            "/layers/:layer_id/field_mapping",
+            put(layer::put_field_mapping),
+        )
+        .route("/layers/:layer_id/versions", get(layer::get_versions))
+        .route(
+            "/layers/:layer_id/versions/:version/rollback",
+            post(layer::rollback_layer),
+        )
+        .route(
+            "/layers/:layer_id/versions/:version/features",
+            get(layer::get_version_features),
+        )
+        .route(
+            "/layers/:layer_id/versions/:version/tiles/:z/:x/:y",
+            get(layer::get_version_tile),
+        )
         .route("/layers/:layer_id/tiles/:z/:x/:y", get(layer::get_tile))
-        .with_state(std::sync::Arc::new(app_state))
+        .with_state(app_state)
         .layer(CorsLayer::permissive()); // Allow CORS for UI requests
 
     // Start the Axum server